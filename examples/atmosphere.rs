@@ -0,0 +1,57 @@
+//! An example featuring clouds in Bevy's physically based atmosphere during a sunset.
+use bevy::{
+    camera::Exposure,
+    light::light_consts::lux,
+    pbr::{Atmosphere, ScatteringMedium},
+    prelude::*,
+};
+use bevy_volumetric_clouds::{CloudsPlugin, config::VolumetricClouds, light::CloudsSun};
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .add_systems(Startup, setup)
+        .add_systems(Update, move_sun)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut std_materials: ResMut<Assets<StandardMaterial>>,
+    mut scattering_media: ResMut<Assets<ScatteringMedium>>,
+) {
+    // The atmosphere renders the sky and lights the clouds with the light that has `CloudsSun`.
+    commands.spawn((
+        Camera3d::default(),
+        Atmosphere::earthlike(scattering_media.add(ScatteringMedium::default())),
+        Exposure::SUNLIGHT,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 3.0, 0.0).looking_to(Vec3::X + Vec3::Y * 0.1, Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: lux::RAW_SUNLIGHT,
+            ..default()
+        },
+        CloudsSun,
+    ));
+
+    // Spawn ground plane
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
+        MeshMaterial3d(std_materials.add(Color::srgb_u8(124, 144, 255))),
+    ));
+}
+
+/// Let the sun set in front of the camera and rise again.
+fn move_sun(time: Res<Time>, mut lights: Query<&mut Transform, With<CloudsSun>>) {
+    let elevation = 0.35 * ops::cos(time.elapsed_secs() * 0.1) + 0.25;
+    for mut transform in &mut lights {
+        *transform = Transform::default().looking_to(
+            -Vec3::new(ops::cos(elevation), ops::sin(elevation), 0.0),
+            Vec3::Y,
+        );
+    }
+}
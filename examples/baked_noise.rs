@@ -0,0 +1,56 @@
+//! An example which bakes the noise of the clouds to files on the first run, and loads them on
+//! later runs instead of generating the noise again.
+use std::path::Path;
+
+use bevy::{prelude::*, render::view::Hdr};
+use bevy_volumetric_clouds::{
+    CloudsPlugin,
+    config::VolumetricClouds,
+    noise::{CloudsNoise, ExportCloudsNoise},
+};
+
+const ATLAS_PATH: &str = "clouds_atlas.ktx2";
+const WORLEY_PATH: &str = "clouds_worley.ktx2";
+const CURL_PATH: &str = "clouds_curl.ktx2";
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut exports: MessageWriter<ExportCloudsNoise>,
+) {
+    let assets = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets");
+    if [ATLAS_PATH, WORLEY_PATH, CURL_PATH]
+        .iter()
+        .all(|path| assets.join(path).exists())
+    {
+        commands.insert_resource(CloudsNoise::load(
+            &asset_server,
+            ATLAS_PATH,
+            WORLEY_PATH,
+            CURL_PATH,
+        ));
+    } else {
+        if let Err(error) = std::fs::create_dir_all(&assets) {
+            error!("Failed to create {}: {error}", assets.display());
+        }
+        exports.write(ExportCloudsNoise {
+            atlas_path: assets.join(ATLAS_PATH),
+            worley_path: assets.join(WORLEY_PATH),
+            curl_path: assets.join(CURL_PATH),
+        });
+    }
+
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 3.0, 0.0).looking_to(Vec3::NEG_Z + Vec3::Y * 0.4, Vec3::Y),
+    ));
+}
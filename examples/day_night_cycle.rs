@@ -0,0 +1,59 @@
+//! An example featuring clouds during a day/night cycle, which passes a day in four minutes.
+use bevy::{prelude::*, render::view::Hdr};
+use bevy_volumetric_clouds::{
+    CloudsPlugin,
+    config::VolumetricClouds,
+    light::CloudsSun,
+    time_of_day::{Sunrise, Sunset, TimeOfDay},
+};
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .insert_resource(TimeOfDay {
+            hour: 17.0,
+            latitude: 52.0,
+            longitude: 5.0,
+            time_scale: 360.0,
+            ..default()
+        })
+        .add_systems(Startup, setup)
+        .add_systems(Update, log_sunrise_and_sunset)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut std_materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Looking towards the west, where the sun sets.
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 3.0, 0.0).looking_to(Vec3::NEG_X + Vec3::Y * 0.2, Vec3::Y),
+    ));
+
+    // `TimeOfDay` moves the light with `CloudsSun` across the sky.
+    commands.spawn(CloudsSun);
+
+    // Spawn ground plane
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
+        MeshMaterial3d(std_materials.add(Color::srgb_u8(124, 144, 255))),
+    ));
+}
+
+fn log_sunrise_and_sunset(
+    time_of_day: Res<TimeOfDay>,
+    mut sunrises: MessageReader<Sunrise>,
+    mut sunsets: MessageReader<Sunset>,
+) {
+    for _ in sunrises.read() {
+        info!("Sunrise at {:.2} h UTC", time_of_day.hour);
+    }
+    for _ in sunsets.read() {
+        info!("Sunset at {:.2} h UTC", time_of_day.hour);
+    }
+}
@@ -6,9 +6,9 @@ use bevy::prelude::*;
 use bevy::render::view::Hdr;
 #[cfg(feature = "debug")]
 use bevy_egui::EguiPlugin;
-use bevy_volumetric_clouds::CloudsPlugin;
 #[cfg(feature = "fly_camera")]
 use bevy_volumetric_clouds::fly_camera::{FlyCam, FlyCameraPlugin};
+use bevy_volumetric_clouds::{CloudsPlugin, config::VolumetricClouds, light::CloudsSun};
 
 fn close_on_esc(
     mut commands: Commands,
@@ -45,11 +45,21 @@ fn setup(
     commands.spawn((
         Camera3d::default(),
         Hdr,
+        VolumetricClouds::default(),
         #[cfg(feature = "fly_camera")]
         FlyCam,
         Transform::from_translation(Vec3::new(0.0, 3.0, 0.0)).looking_to(Vec3::X, Vec3::Y),
     ));
 
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::FULL_DAYLIGHT,
+            ..default()
+        },
+        Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        CloudsSun,
+    ));
+
     // Spawn ground plane
     commands.spawn((
         Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
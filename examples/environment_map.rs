@@ -0,0 +1,70 @@
+//! An example featuring reflections and ambient lighting that follow the clouds, using a cubemap
+//! of the sky and the clouds which is updated one face per frame.
+use bevy::{camera::Exposure, light::GeneratedEnvironmentMapLight, prelude::*, render::view::Hdr};
+use bevy_volumetric_clouds::{
+    CloudsPlugin, config::VolumetricClouds, environment_map::CloudsEnvironmentMap, light::CloudsSun,
+};
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut std_materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let environment_map = CloudsEnvironmentMap {
+        faces_per_frame: 1,
+        ..CloudsEnvironmentMap::new(&mut images, 256)
+    };
+    let exposure = Exposure::default();
+
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        VolumetricClouds::default(),
+        GeneratedEnvironmentMapLight {
+            environment_map: environment_map.image.clone(),
+            intensity: exposure.exposure().recip(),
+            ..default()
+        },
+        environment_map,
+        exposure,
+        Transform::from_xyz(0.0, 2.0, 6.0).looking_at(Vec3::new(0.0, 1.5, 0.0), Vec3::Y),
+    ));
+
+    // A mirror-like sphere and a rough sphere
+    let sphere = meshes.add(Sphere::new(1.0));
+    for (x, perceptual_roughness) in [(-1.5, 0.05), (1.5, 0.8)] {
+        commands.spawn((
+            Mesh3d(sphere.clone()),
+            MeshMaterial3d(std_materials.add(StandardMaterial {
+                base_color: Color::WHITE,
+                metallic: 1.0,
+                perceptual_roughness,
+                ..default()
+            })),
+            Transform::from_xyz(x, 1.5, 0.0),
+        ));
+    }
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::FULL_DAYLIGHT,
+            ..default()
+        },
+        Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        CloudsSun,
+    ));
+
+    // Spawn ground plane
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
+        MeshMaterial3d(std_materials.add(Color::srgb_u8(124, 144, 255))),
+    ));
+}
@@ -0,0 +1,27 @@
+//! An example featuring low cumulus under a layer of altocumulus and high cirrus.
+use bevy::{prelude::*, render::view::Hdr};
+use bevy_volumetric_clouds::{
+    CloudsPlugin,
+    config::VolumetricClouds,
+    layers::{CloudLayer, CloudsLayers},
+};
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        // The main layer of `CloudsConfig` holds the cumulus.
+        .insert_resource(CloudsLayers {
+            layers: vec![CloudLayer::altocumulus(), CloudLayer::cirrus()],
+        })
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 3.0, 0.0).looking_to(Vec3::NEG_Z + Vec3::Y * 0.4, Vec3::Y),
+    ));
+}
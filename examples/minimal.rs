@@ -1,6 +1,6 @@
 //! A minimal example featuring clouds.
 use bevy::{prelude::*, render::view::Hdr};
-use bevy_volumetric_clouds::CloudsPlugin;
+use bevy_volumetric_clouds::{CloudsPlugin, config::VolumetricClouds};
 
 fn main() {
     App::new()
@@ -10,5 +10,5 @@ fn main() {
 }
 
 fn setup(mut commands: Commands) {
-    commands.spawn((Camera3d::default(), Hdr));
+    commands.spawn((Camera3d::default(), Hdr, VolumetricClouds::default()));
 }
@@ -0,0 +1,74 @@
+//! An example featuring clouds rendered by a camera which targets an image, like a security-camera
+//! screen, next to the clouds of the main camera.
+use bevy::{
+    camera::RenderTarget, prelude::*, render::render_resource::TextureFormat, render::view::Hdr,
+};
+use bevy_volumetric_clouds::{CloudsPlugin, config::VolumetricClouds, light::CloudsSun};
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut std_materials: ResMut<Assets<StandardMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    // The image the screen camera renders to, its size is independent of the window.
+    let image = images.add(Image::new_target_texture(
+        640,
+        360,
+        TextureFormat::Rgba8Unorm,
+        Some(TextureFormat::Rgba8UnormSrgb),
+    ));
+
+    // The screen camera looks in another direction than the main camera and renders first.
+    commands.spawn((
+        Camera3d::default(),
+        Camera {
+            order: -1,
+            ..default()
+        },
+        RenderTarget::Image(image.clone().into()),
+        Hdr,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 3.0, 0.0).looking_to(Vec3::NEG_X + Vec3::Y * 0.2, Vec3::Y),
+    ));
+
+    // The screen which shows the image, in front of the main camera.
+    commands.spawn((
+        Mesh3d(meshes.add(Rectangle::new(3.2, 1.8))),
+        MeshMaterial3d(std_materials.add(StandardMaterial {
+            base_color_texture: Some(image),
+            unlit: true,
+            ..default()
+        })),
+        Transform::from_xyz(0.0, 3.0, -6.0),
+    ));
+
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 3.0, 0.0).looking_to(Vec3::NEG_Z, Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::FULL_DAYLIGHT,
+            ..default()
+        },
+        Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        CloudsSun,
+    ));
+
+    // Spawn ground plane
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
+        MeshMaterial3d(std_materials.add(Color::srgb_u8(124, 144, 255))),
+    ));
+}
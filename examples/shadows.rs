@@ -0,0 +1,51 @@
+//! An example featuring clouds which cast shadows onto the ground as they drift by.
+use bevy::{prelude::*, render::view::Hdr};
+use bevy_volumetric_clouds::{
+    CloudsPlugin,
+    config::VolumetricClouds,
+    light::CloudsSun,
+    shadows::{CloudsShadowMap, CloudsShadows, CloudsShadowsMaterial},
+};
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<CloudsShadowsMaterial>>,
+) {
+    // The shadows are rendered for a region of 16 km around the camera.
+    commands.insert_resource(CloudsShadowMap::new(&mut images, 512, 16_000.0));
+
+    // Looking down on the ground from a hill.
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 300.0, 0.0).looking_to(Vec3::X - Vec3::Y * 0.3, Vec3::Y),
+    ));
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::FULL_DAYLIGHT,
+            ..default()
+        },
+        Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        CloudsSun,
+    ));
+
+    // Spawn ground plane, which is shadowed by the clouds
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
+        MeshMaterial3d(materials.add(CloudsShadowsMaterial {
+            base: Color::srgb_u8(124, 144, 255).into(),
+            extension: CloudsShadows::default(),
+        })),
+    ));
+}
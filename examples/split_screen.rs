@@ -0,0 +1,71 @@
+//! An example featuring clouds in two viewports of the same window (local co-op split screen).
+use bevy::{camera::Viewport, prelude::*, render::view::Hdr, window::WindowResized};
+use bevy_volumetric_clouds::{CloudsPlugin, config::VolumetricClouds, light::CloudsSun};
+
+/// Index of the viewport of a camera, from left to right.
+#[derive(Component)]
+struct Player(u32);
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .add_systems(Startup, setup)
+        .add_systems(Update, set_camera_viewports)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut std_materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (index, direction) in [Vec3::X, Vec3::NEG_Z].into_iter().enumerate() {
+        commands.spawn((
+            Camera3d::default(),
+            Camera {
+                order: index as isize,
+                ..default()
+            },
+            Hdr,
+            VolumetricClouds::default(),
+            Player(index as u32),
+            Transform::from_translation(Vec3::new(0.0, 3.0, 0.0)).looking_to(direction, Vec3::Y),
+        ));
+    }
+
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::FULL_DAYLIGHT,
+            ..default()
+        },
+        Transform::from_xyz(1.0, 1.0, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+        CloudsSun,
+    ));
+
+    // Spawn ground plane
+    commands.spawn((
+        Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
+        MeshMaterial3d(std_materials.add(Color::srgb_u8(124, 144, 255))),
+    ));
+}
+
+fn set_camera_viewports(
+    windows: Query<&Window>,
+    mut resize_events: MessageReader<WindowResized>,
+    mut cameras: Query<(&Player, &mut Camera)>,
+) {
+    for resize_event in resize_events.read() {
+        let Ok(window) = windows.get(resize_event.window) else {
+            continue;
+        };
+        let size = window.physical_size() / UVec2::new(2, 1);
+
+        for (player, mut camera) in &mut cameras {
+            camera.viewport = Some(Viewport {
+                physical_position: UVec2::new(player.0 * size.x, 0),
+                physical_size: size,
+                ..default()
+            });
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! An example featuring a weather map with clear skies to the west and a storm to the east.
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        view::Hdr,
+    },
+};
+use bevy_volumetric_clouds::{CloudsPlugin, config::VolumetricClouds, weather::CloudsWeatherMap};
+
+/// Width and height of the weather map in pixels.
+const SIZE: u32 = 64;
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, CloudsPlugin))
+        .add_systems(Startup, setup)
+        .run();
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    // Looking towards the north, with the storm on the right.
+    commands.spawn((
+        Camera3d::default(),
+        Hdr,
+        VolumetricClouds::default(),
+        Transform::from_xyz(0.0, 3.0, 0.0).looking_to(Vec3::NEG_Z + Vec3::Y * 0.2, Vec3::Y),
+    ));
+
+    // Usually painted by hand, here the coverage increases towards the east while a round storm of
+    // wet cumulonimbus lies to the north-east. The type goes from stratus in the west to cumulus in
+    // the east, following the default `CloudsProfiles`.
+    let mut data = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let uv = (Vec2::new(x as f32, y as f32) + 0.5) / SIZE as f32;
+            let storm = (1.0 - uv.distance(Vec2::new(0.75, 0.3)) * 4.0).clamp(0.0, 1.0);
+            let coverage = (0.3 + 0.3 * uv.x + 0.4 * storm).min(1.0);
+            let cloud_type = (uv.x * 2.0 / 3.0 + storm).min(1.0);
+            data.extend([coverage, cloud_type, storm, 1.0].map(|v| (v * 255.0) as u8));
+        }
+    }
+    let image = Image::new(
+        Extent3d {
+            width: SIZE,
+            height: SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+
+    // The weather map covers 40 km around the camera.
+    commands.insert_resource(CloudsWeatherMap::new(
+        images.add(image),
+        Vec2::splat(40_000.0),
+    ));
+}
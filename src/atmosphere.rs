@@ -0,0 +1,296 @@
+use core::f32::consts::PI;
+
+use bevy::{
+    camera::Exposure,
+    core_pipeline::core_3d::graph::{Core3d, Node3d},
+    pbr::{
+        AtmospherePlugin, ExtractedAtmosphere, ExtractedDirectionalLight, GpuAtmosphereSettings,
+        resources::{AtmosphereSampler, AtmosphereTextures},
+    },
+    prelude::*,
+    render::{
+        Render, RenderApp, RenderSystems,
+        camera::ExtractedCamera,
+        extract_component::ExtractComponentPlugin,
+        render_graph::{InternedRenderLabel, RenderGraph, RenderLabel},
+        render_resource::{
+            BindGroup, BindGroupEntries, DynamicUniformBuffer, PipelineCache, ShaderType,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        view::ExtractedView,
+    },
+};
+
+use crate::{
+    compute::{CloudsLabel, CloudsPipeline, prepare_uniforms_bind_group},
+    config::{CloudsConfig, VolumetricClouds},
+    light::CloudsSun,
+};
+
+/// The inputs of the clouds from Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) of a view, see
+/// `atmosphere.wgsl`.
+#[derive(Clone, ShaderType)]
+pub(crate) struct CloudsAtmosphereUniform {
+    atmosphere_from_world: Mat4,
+    ground_albedo: Vec3,
+    bottom_radius: f32,
+    top_radius: f32,
+    scene_units_to_m: f32,
+    sky_view_lut_size: Vec2,
+    sun_illuminance: Vec3,
+    exposure: f32,
+    moon_illuminance: Vec3,
+}
+
+/// The uniforms of all views with [`VolumetricClouds`] and an atmosphere.
+#[derive(Resource, Default)]
+struct CloudsAtmosphereBuffer(DynamicUniformBuffer<CloudsAtmosphereUniform>);
+
+/// The atmosphere of a view with [`VolumetricClouds`].
+#[derive(Component)]
+pub(crate) struct CloudsAtmosphere {
+    /// Direction towards the directional light that lights the clouds instead of `sun_dir`, like
+    /// it lights Bevy's sky.
+    pub sun_dir: Option<Vec3>,
+    /// Offset of the uniforms of the view in [`CloudsAtmosphereBuffer`].
+    uniform_offset: u32,
+}
+
+#[expect(clippy::too_many_arguments)]
+fn prepare_atmosphere_uniforms(
+    mut commands: Commands,
+    mut atmosphere_buffer: ResMut<CloudsAtmosphereBuffer>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    clouds_config: Res<CloudsConfig>,
+    lights: Query<(&ExtractedDirectionalLight, Has<CloudsSun>)>,
+    views: Query<
+        (
+            Entity,
+            &ExtractedView,
+            &ExtractedCamera,
+            &ExtractedAtmosphere,
+            &GpuAtmosphereSettings,
+        ),
+        With<VolumetricClouds>,
+    >,
+    removed: Query<Entity, (With<CloudsAtmosphere>, Without<ExtractedAtmosphere>)>,
+) {
+    for entity in &removed {
+        commands
+            .entity(entity)
+            .remove::<(CloudsAtmosphere, CloudsAtmosphereBindGroup)>();
+    }
+
+    // The light with `CloudsSun`, or else the brightest light.
+    let sun = lights
+        .iter()
+        .max_by(|(a, a_is_sun), (b, b_is_sun)| {
+            a_is_sun
+                .cmp(b_is_sun)
+                .then(a.illuminance.total_cmp(&b.illuminance))
+        })
+        .map(|(light, _)| light);
+    let sun_dir = sun.map(|light| light.transform.back().as_vec3());
+    let sun_illuminance = sun.map_or(Vec3::ZERO, |light| {
+        light.color.to_vec3() * light.illuminance
+    });
+
+    // There is no light for the moon, its illuminance is recovered from the moon color of the
+    // configuration by undoing the scaling of `sun_color`.
+    let moon_illuminance =
+        clouds_config.moon_color.truncate() * 4.0 * PI / Exposure::default().exposure();
+
+    let buffer = &mut atmosphere_buffer.0;
+    buffer.clear();
+    for (entity, view, camera, atmosphere, settings) in &views {
+        // The sky-view LUT faces the horizontal forward direction of the camera, like in
+        // `prepare_atmosphere_transforms` of Bevy.
+        let world_from_view = view.world_from_view.affine();
+        let atmosphere_z = Vec3::from(world_from_view.matrix3.z_axis)
+            .with_y(0.0)
+            .try_normalize()
+            .unwrap_or_else(|| {
+                Vec3::from(world_from_view.matrix3.y_axis)
+                    .with_y(0.0)
+                    .normalize()
+            });
+        let atmosphere_x = Vec3::Y.cross(atmosphere_z).normalize();
+        let world_from_atmosphere = Mat3::from_cols(atmosphere_x, Vec3::Y, atmosphere_z);
+
+        let uniform_offset = buffer.push(&CloudsAtmosphereUniform {
+            atmosphere_from_world: Mat4::from_mat3(world_from_atmosphere.transpose()),
+            ground_albedo: atmosphere.ground_albedo,
+            bottom_radius: atmosphere.bottom_radius,
+            top_radius: atmosphere.top_radius,
+            scene_units_to_m: settings.scene_units_to_m,
+            sky_view_lut_size: settings.sky_view_lut_size.as_vec2(),
+            sun_illuminance,
+            exposure: camera.exposure,
+            moon_illuminance,
+        });
+        commands.entity(entity).insert(CloudsAtmosphere {
+            sun_dir,
+            uniform_offset,
+        });
+    }
+    buffer.write_buffer(&render_device, &render_queue);
+}
+
+/// The bind group of the atmosphere of a view, with the offset of its uniforms.
+#[derive(Component)]
+pub(crate) struct CloudsAtmosphereBindGroup {
+    pub bind_group: BindGroup,
+    pub uniform_offset: u32,
+}
+
+fn prepare_atmosphere_bind_groups(
+    mut commands: Commands,
+    pipeline: Res<CloudsPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    atmosphere_buffer: Res<CloudsAtmosphereBuffer>,
+    sampler: Option<Res<AtmosphereSampler>>,
+    render_device: Res<RenderDevice>,
+    views: Query<(Entity, &CloudsAtmosphere, &AtmosphereTextures)>,
+) {
+    let (Some(binding), Some(sampler)) = (atmosphere_buffer.0.binding(), sampler) else {
+        return;
+    };
+
+    for (entity, atmosphere, textures) in &views {
+        let bind_group = render_device.create_bind_group(
+            "clouds_atmosphere_bind_group",
+            &pipeline_cache.get_bind_group_layout(&pipeline.atmosphere_bind_group_layout),
+            &BindGroupEntries::sequential((
+                binding.clone(),
+                &textures.transmittance_lut.default_view,
+                &textures.sky_view_lut.default_view,
+                &**sampler,
+            )),
+        );
+        commands.entity(entity).insert(CloudsAtmosphereBindGroup {
+            bind_group,
+            uniform_offset: atmosphere.uniform_offset,
+        });
+    }
+}
+
+/// The nodes of the `Core3d` graph which render the sky of the atmosphere.
+///
+/// Their label isn't public, but `AtmospherePlugin` adds its node with edges from
+/// [`Node3d::MainOpaquePass`] to [`Node3d::MainTransparentPass`], like the transmissive pass. Any
+/// other node with these edges draws after the opaque scene as well, so it's drawn before the
+/// clouds too.
+fn find_sky_nodes(core_3d: &RenderGraph) -> Vec<InternedRenderLabel> {
+    let (opaque, transmissive, transparent, clouds) = (
+        Node3d::MainOpaquePass.intern(),
+        Node3d::MainTransmissivePass.intern(),
+        Node3d::MainTransparentPass.intern(),
+        CloudsLabel::Render.intern(),
+    );
+    core_3d
+        .iter_nodes()
+        .filter(|node| {
+            let inputs = node.edges.input_edges();
+            ![transmissive, clouds].contains(&node.label)
+                && inputs.iter().any(|edge| edge.get_output_node() == opaque)
+                && !inputs
+                    .iter()
+                    .any(|edge| [transmissive, clouds].contains(&edge.get_output_node()))
+                && node
+                    .edges
+                    .output_edges()
+                    .iter()
+                    .any(|edge| edge.get_input_node() == transparent)
+        })
+        .map(|node| node.label)
+        .collect()
+}
+
+/// A plugin which lets the clouds of views with Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) use
+/// the LUTs of the atmosphere for their lighting, instead of the hand-tuned sky.
+pub(crate) struct CloudsAtmospherePlugin;
+
+impl Plugin for CloudsAtmospherePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ExtractComponentPlugin::<CloudsSun>::default());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .init_resource::<CloudsAtmosphereBuffer>()
+            .add_systems(
+                Render,
+                (
+                    prepare_atmosphere_uniforms
+                        .in_set(RenderSystems::PrepareResources)
+                        .before(prepare_uniforms_bind_group),
+                    prepare_atmosphere_bind_groups.in_set(RenderSystems::PrepareBindGroups),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        // The sky of the atmosphere is rendered after the opaque pass as well, the clouds have to
+        // be composited over it.
+        let atmosphere = app.is_plugin_added::<AtmospherePlugin>();
+        let render_app = app.sub_app_mut(RenderApp);
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        let Some(core_3d) = render_graph.get_sub_graph_mut(Core3d) else {
+            return;
+        };
+        let skies = find_sky_nodes(core_3d);
+        for sky in &skies {
+            core_3d.add_node_edge(*sky, CloudsLabel::Render);
+        }
+        if skies.is_empty() && atmosphere {
+            warn!(
+                "The node which renders the sky of the atmosphere wasn't found, the clouds may be \
+                drawn behind the sky of cameras with an `Atmosphere`. Add `CloudsPlugin` after \
+                `DefaultPlugins`."
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_graph::EmptyNode;
+
+    use super::*;
+
+    #[derive(RenderLabel, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    struct RenderSky;
+
+    #[test]
+    fn finds_the_sky_between_the_opaque_and_transparent_passes() {
+        // The main passes of `Core3d` with the clouds and the sky of the atmosphere.
+        let mut core_3d = RenderGraph::default();
+        for label in [
+            Node3d::MainOpaquePass.intern(),
+            Node3d::MainTransmissivePass.intern(),
+            Node3d::MainTransparentPass.intern(),
+            CloudsLabel::Render.intern(),
+        ] {
+            core_3d.add_node(label, EmptyNode);
+        }
+        core_3d.add_node_edges((
+            Node3d::MainOpaquePass,
+            Node3d::MainTransmissivePass,
+            Node3d::MainTransparentPass,
+        ));
+        core_3d.add_node_edges((
+            Node3d::MainOpaquePass,
+            CloudsLabel::Render,
+            Node3d::MainTransmissivePass,
+        ));
+        assert!(find_sky_nodes(&core_3d).is_empty());
+
+        core_3d.add_node(RenderSky, EmptyNode);
+        core_3d.add_node_edges((
+            Node3d::MainOpaquePass,
+            RenderSky,
+            Node3d::MainTransparentPass,
+        ));
+        assert_eq!(find_sky_nodes(&core_3d), [RenderSky.intern()]);
+    }
+}
@@ -1,230 +1,753 @@
 use bevy::{
     asset::load_embedded_asset,
-    ecs::system::ResMut,
+    ecs::{entity::EntityHashMap, system::ResMut},
     prelude::*,
     render::{
         Extract, Render, RenderApp, RenderSystems,
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
         extract_resource::ExtractResourcePlugin,
         render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, RenderLabel},
         render_resource::{
-            AsBindGroup, BindGroup, BindGroupEntries, BindGroupLayoutDescriptor,
-            BindGroupLayoutEntries, CachedComputePipelineId, CachedPipelineState,
-            ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, ShaderStages,
-            binding_types::uniform_buffer,
+            BindGroup, BindGroupEntries, BindGroupLayoutDescriptor, BindGroupLayoutEntries,
+            CachedComputePipelineId, CachedPipelineState, ComputePassDescriptor,
+            ComputePipelineDescriptor, PipelineCache, SamplerBindingType, ShaderStages,
+            SpecializedComputePipeline, SpecializedComputePipelines, StorageTextureAccess, Texture,
+            TextureFormat, TextureSampleType, TextureViewDescriptor,
+            binding_types::{
+                sampler, texture_2d, texture_3d, texture_depth_2d, texture_depth_2d_multisampled,
+                texture_storage_2d, texture_storage_3d, uniform_buffer,
+            },
         },
-        renderer::{RenderContext, RenderDevice, RenderQueue},
+        renderer::{RenderAdapter, RenderContext, RenderDevice, RenderQueue},
         texture::GpuImage,
+        view::ExtractedView,
     },
+    shader::ShaderDefVal,
 };
 /// Controls the compute shader which renders the volumetric clouds.
-use std::borrow::Cow;
-
-use crate::config::CloudsConfig;
+use core::sync::atomic::{AtomicBool, Ordering};
+use std::{borrow::Cow, sync::Arc};
+
+use crate::{
+    atmosphere::{CloudsAtmosphere, CloudsAtmosphereUniform},
+    config::{CloudsConfig, VolumetricClouds},
+    environment_map::{EnvironmentMapFaces, face_inverse_view},
+    layers::{CloudLayerMode, CloudsLayers, MAX_CLOUD_LAYERS},
+    profiles::CloudsProfilesImage,
+    shadows::PreparedShadowMap,
+    weather::PreparedWeatherMap,
+};
 
 use super::{
-    images::IMAGE_SIZE,
-    uniforms::{CloudsImage, CloudsUniform, CloudsUniformBuffer},
+    images::{ATLAS_FORMAT, ATLAS_SIZE, CURL_FORMAT, CloudsTextureFormats, DEPTH_FORMAT},
+    uniforms::{CloudsNoiseImages, CloudsRenderImages, CloudsUniform, CloudsUniformBuffer},
 };
 
-const WORKGROUP_SIZE: u32 = 8;
+pub(crate) const WORKGROUP_SIZE: u32 = 8;
 
-#[derive(Resource, Clone, Copy)]
+/// Width, height and depth of the workgroups which generate the 3D Worley noise.
+const WORLEY_WORKGROUP_SIZE: u32 = 4;
+
+/// The matrices of a camera with [`VolumetricClouds`], used to compute the direction of the rays
+/// and to reproject the clouds of the previous frame.
+#[derive(Component, Clone, Copy, Default, ExtractComponent)]
 pub(crate) struct CameraMatrices {
     pub translation: Vec3,
     pub inverse_camera_view: Mat4,
     pub inverse_camera_projection: Mat4,
+    pub clip_from_world: Mat4,
+    /// The `translation` of the previous frame, used for reprojection.
+    pub previous_translation: Vec3,
+    /// The `clip_from_world` of the previous frame, used for reprojection.
+    pub previous_clip_from_world: Mat4,
 }
 
 #[derive(Resource)]
-struct CloudsUniformBindGroup(BindGroup);
+pub(crate) struct CloudsUniformBindGroup(pub BindGroup);
+
+/// Offset of the uniforms of a view in [`CloudsUniformBuffer`].
+#[derive(Component)]
+pub(crate) struct CloudsUniformOffset(pub u32);
 
 #[derive(Resource)]
-struct CloudsImageBindGroup(BindGroup);
+pub(crate) struct CloudsNoiseBindGroup(pub BindGroup);
 
-#[expect(clippy::too_many_arguments)]
-fn prepare_uniforms_bind_group(
+/// The bind groups which generate the noise, which only exist until generated noise has been
+/// generated.
+#[derive(Resource)]
+pub(crate) struct CloudsInitBindGroups {
+    /// The bind group of the `init` and `init_worley` entry points.
+    noise: BindGroup,
+    /// Width, height and depth of the first mip level of the Worley noise.
+    worley_size: u32,
+    /// The bind groups of the `downsample_worley` entry point, which write each mip level of the
+    /// Worley noise after the first.
+    worley_mips: Vec<BindGroup>,
+}
+
+/// Inserted once the noise textures are ready, which happens before any views are rendered in the
+/// same frame. Generated noise is ready in the frame it gets generated, loaded noise as soon as it
+/// has been loaded.
+#[derive(Resource)]
+pub(crate) struct CloudsNoiseReady;
+
+/// The bind group of the [`CloudsRenderImages`] of a view.
+#[derive(Component)]
+pub(crate) struct CloudsViewImages {
+    pub bind_group: BindGroup,
+    /// The `cloud_render_image` the bind group was created for.
+    render_image: AssetId<Image>,
+    /// Size of the `cloud_render_image` the bind group was created for.
+    pub render_size: UVec2,
+    /// Pairs of textures that are copied at the end of each frame to become the history of the
+    /// next frame.
+    pub history_copies: [(Texture, Texture); 2],
+}
+
+#[expect(clippy::too_many_arguments, clippy::type_complexity)]
+pub(crate) fn prepare_uniforms_bind_group(
     mut commands: Commands,
     pipeline: Res<CloudsPipeline>,
     pipeline_cache: Res<PipelineCache>,
     render_queue: Res<RenderQueue>,
     mut clouds_uniform_buffer: ResMut<CloudsUniformBuffer>,
-    camera: ResMut<CameraMatrices>,
     clouds_config: Res<CloudsConfig>,
+    layers: Res<CloudsLayers>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &VolumetricClouds,
+        &CameraMatrices,
+        &CloudsViewImages,
+        Option<&CloudsAtmosphere>,
+    )>,
+    mut environment_maps: Query<&mut EnvironmentMapFaces>,
+    mut shadow_map: Option<ResMut<PreparedShadowMap>>,
+    weather_map: Option<Res<PreparedWeatherMap>>,
+    noise_ready: Option<Res<CloudsNoiseReady>>,
     render_device: Res<RenderDevice>,
     time: Res<Time>,
+    mut previous_render_images: Local<EntityHashMap<AssetId<Image>>>,
 ) {
-    let buffer = clouds_uniform_buffer.buffer.get_mut();
-
-    buffer.clouds_raymarch_steps_count = clouds_config.clouds_raymarch_steps_count;
-    buffer.planet_radius = clouds_config.planet_radius;
-    buffer.clouds_bottom_height = clouds_config.clouds_bottom_height;
-    buffer.clouds_top_height = clouds_config.clouds_top_height;
-    buffer.clouds_coverage = clouds_config.clouds_coverage;
-    buffer.clouds_detail_strength = clouds_config.clouds_detail_strength;
-    buffer.clouds_base_edge_softness = clouds_config.clouds_base_edge_softness;
-    buffer.clouds_bottom_softness = clouds_config.clouds_bottom_softness;
-    buffer.clouds_density = clouds_config.clouds_density;
-    buffer.clouds_shadow_raymarch_steps_count = clouds_config.clouds_shadow_raymarch_steps_count;
-    buffer.clouds_shadow_raymarch_step_size = clouds_config.clouds_shadow_raymarch_step_size;
-    buffer.clouds_shadow_raymarch_step_multiply =
+    let CloudsUniformBuffer { uniform, buffer } = clouds_uniform_buffer.as_mut();
+
+    uniform.planet_radius = clouds_config.planet_radius;
+    uniform.clouds_bottom_height = clouds_config.clouds_bottom_height;
+    uniform.clouds_top_height = clouds_config.clouds_top_height;
+    uniform.clouds_coverage = clouds_config.clouds_coverage;
+    uniform.cloud_type = clouds_config.cloud_type;
+    uniform.clouds_detail_strength = clouds_config.clouds_detail_strength;
+    uniform.clouds_curl_strength = clouds_config.clouds_curl_strength;
+    uniform.clouds_curl_scale = clouds_config.clouds_curl_scale;
+    uniform.clouds_base_edge_softness = clouds_config.clouds_base_edge_softness;
+    uniform.clouds_bottom_softness = clouds_config.clouds_bottom_softness;
+    uniform.clouds_density = clouds_config.clouds_density;
+    uniform.clouds_shadow_raymarch_step_size = clouds_config.clouds_shadow_raymarch_step_size;
+    uniform.clouds_shadow_raymarch_step_multiply =
         clouds_config.clouds_shadow_raymarch_step_multiply;
-    buffer.forward_scattering_g = clouds_config.forward_scattering_g;
-    buffer.backward_scattering_g = clouds_config.backward_scattering_g;
-    buffer.scattering_lerp = clouds_config.scattering_lerp;
-    buffer.clouds_ambient_color_top = clouds_config.clouds_ambient_color_top;
-    buffer.clouds_ambient_color_bottom = clouds_config.clouds_ambient_color_bottom;
-    buffer.clouds_min_transmittance = clouds_config.clouds_min_transmittance;
-    buffer.clouds_base_scale = clouds_config.clouds_base_scale;
-    buffer.clouds_detail_scale = clouds_config.clouds_detail_scale;
-    buffer.sun_dir = clouds_config.sun_dir;
-    buffer.sun_color = clouds_config.sun_color;
-    buffer.camera_translation = camera.translation;
-    buffer.time = time.elapsed_secs_wrapped();
-    buffer.reprojection_strength = clouds_config.reprojection_strength;
-    buffer.inverse_camera_view = camera.inverse_camera_view;
-    buffer.inverse_camera_projection = camera.inverse_camera_projection;
-    buffer.wind_displacement += time.delta_secs() * clouds_config.wind_velocity;
-
-    clouds_uniform_buffer
-        .buffer
-        .write_buffer(&render_device, &render_queue);
+    uniform.forward_scattering_g = clouds_config.forward_scattering_g;
+    uniform.backward_scattering_g = clouds_config.backward_scattering_g;
+    uniform.scattering_lerp = clouds_config.scattering_lerp;
+    uniform.multiple_scattering_octaves = clouds_config.multiple_scattering_octaves;
+    uniform.multiple_scattering_attenuation = clouds_config.multiple_scattering_attenuation;
+    uniform.multiple_scattering_contribution = clouds_config.multiple_scattering_contribution;
+    uniform.multiple_scattering_eccentricity = clouds_config.multiple_scattering_eccentricity;
+    uniform.powder_strength = clouds_config.powder_strength;
+    uniform.clouds_ambient_color_top = clouds_config.clouds_ambient_color_top;
+    uniform.clouds_ambient_color_bottom = clouds_config.clouds_ambient_color_bottom;
+    uniform.clouds_min_transmittance = clouds_config.clouds_min_transmittance;
+    uniform.clouds_base_scale = clouds_config.clouds_base_scale;
+    uniform.clouds_detail_scale = clouds_config.clouds_detail_scale;
+    uniform.sun_dir = clouds_config.sun_dir;
+    uniform.sun_color = clouds_config.sun_color;
+    uniform.moon_dir = clouds_config.moon_dir;
+    uniform.moon_color = clouds_config.moon_color;
+    uniform.stars_intensity = clouds_config.stars_intensity;
+    uniform.crepuscular_rays_intensity = clouds_config.crepuscular_rays_intensity;
+    uniform.crepuscular_rays_decay = clouds_config.crepuscular_rays_decay;
+    uniform.crepuscular_rays_samples = clouds_config.crepuscular_rays_samples;
+    if let Some(weather_map) = weather_map {
+        uniform.weather_map_center = weather_map.center;
+        uniform.weather_map_extent = weather_map.extent;
+    }
+    uniform.time = time.elapsed_secs_wrapped();
+    uniform.reprojection_strength = clouds_config.reprojection_strength;
+    uniform.previous_wind_displacement = uniform.wind_displacement;
+    uniform.wind_displacement += time.delta_secs() * clouds_config.wind_velocity;
+    uniform.layer_count = layers.layers.len().min(MAX_CLOUD_LAYERS) as u32;
+    for (layer, layer_uniform) in layers.layers.iter().zip(&mut uniform.layers) {
+        layer_uniform.previous_wind_displacement = layer_uniform.wind_displacement;
+        layer_uniform.wind_displacement += time.delta_secs() * layer.wind_velocity;
+        layer_uniform.bottom_height = layer.bottom_height;
+        layer_uniform.top_height = layer.top_height;
+        layer_uniform.coverage = layer.coverage;
+        layer_uniform.density = layer.density;
+        layer_uniform.base_scale = layer.base_scale;
+        layer_uniform.detail_scale = layer.detail_scale;
+        layer_uniform.cloud_type = layer.cloud_type;
+        layer_uniform.mode = match layer.mode {
+            CloudLayerMode::Volumetric => 0,
+            CloudLayerMode::Flat => 1,
+        };
+        layer_uniform.use_weather_map = layer.use_weather_map.into();
+    }
+    uniform.frame_index = uniform.frame_index.wrapping_add(1);
+
+    buffer.clear();
+    let mut render_images = EntityHashMap::default();
+    for (entity, view, volumetric_clouds, camera, view_images, atmosphere) in &views {
+        let config = volumetric_clouds.apply_to(*clouds_config);
+        let mut view_uniform = uniform.clone();
+        view_uniform.clouds_raymarch_steps_count = config.clouds_raymarch_steps_count;
+        view_uniform.clouds_shadow_raymarch_steps_count = config.clouds_shadow_raymarch_steps_count;
+        view_uniform.interleave_tile_size = config.interleaving.tile_size();
+        view_uniform.camera_translation = camera.translation;
+        view_uniform.inverse_camera_view = camera.inverse_camera_view;
+        view_uniform.inverse_camera_projection = camera.inverse_camera_projection;
+        view_uniform.previous_camera_translation = camera.previous_translation;
+        view_uniform.clip_from_world = camera.clip_from_world;
+        view_uniform.previous_clip_from_world = camera.previous_clip_from_world;
+        view_uniform.render_resolution = view_images.render_size.as_vec2();
+        view_uniform.viewport = view.viewport.as_vec4();
+        if let Some(sun_dir) = atmosphere.and_then(|atmosphere| atmosphere.sun_dir) {
+            view_uniform.sun_dir = sun_dir.extend(0.0);
+        }
+
+        // A freshly allocated render image doesn't contain anything that can be reused. Neither
+        // does the history of a view that was skipped last frame because the noise wasn't ready,
+        // the readiness of the noise is only updated after this system.
+        view_uniform.history_valid = (noise_ready.is_some()
+            && previous_render_images.get(&entity) == Some(&view_images.render_image))
+        .into();
+        render_images.insert(entity, view_images.render_image);
+
+        let offset = buffer.push(&view_uniform);
+        commands.entity(entity).insert(CloudsUniformOffset(offset));
+    }
+    *previous_render_images = render_images;
+
+    // Each face of a cubemap is rendered like a square view with a field of view of 90°.
+    for mut environment_map in &mut environment_maps {
+        let EnvironmentMapFaces {
+            origin,
+            size,
+            faces,
+        } = environment_map.as_mut();
+        for face in faces {
+            let mut face_uniform = uniform.clone();
+            face_uniform.clouds_raymarch_steps_count = clouds_config.clouds_raymarch_steps_count;
+            face_uniform.clouds_shadow_raymarch_steps_count =
+                clouds_config.clouds_shadow_raymarch_steps_count;
+            face_uniform.camera_translation = *origin;
+            face_uniform.inverse_camera_view = face_inverse_view(*origin, face.index);
+            face_uniform.inverse_camera_projection = Mat4::IDENTITY;
+            face_uniform.render_resolution = Vec2::splat(*size as f32);
+            face.uniform_offset = buffer.push(&face_uniform);
+        }
+    }
 
+    if let Some(shadow_map) = shadow_map.as_deref_mut() {
+        let mut shadow_map_uniform = uniform.clone();
+        shadow_map_uniform.shadow_map_center = shadow_map.center;
+        shadow_map_uniform.shadow_map_extent = shadow_map.extent;
+        shadow_map.uniform_offset = buffer.push(&shadow_map_uniform);
+    }
+
+    buffer.write_buffer(&render_device, &render_queue);
+
+    let Some(binding) = buffer.binding() else {
+        return;
+    };
     let bind_group_uniforms = render_device.create_bind_group(
         None,
         &pipeline_cache.get_bind_group_layout(&pipeline.uniform_bind_group_layout),
-        &BindGroupEntries::single(clouds_uniform_buffer.buffer.binding().unwrap().clone()),
+        &BindGroupEntries::single(binding),
     );
     commands.insert_resource(CloudsUniformBindGroup(bind_group_uniforms));
 }
 
-fn prepare_textures_bind_group(
+#[expect(clippy::too_many_arguments)]
+pub(crate) fn prepare_noise_bind_group(
     mut commands: Commands,
     pipeline: Res<CloudsPipeline>,
     pipeline_cache: Res<PipelineCache>,
     gpu_images: Res<RenderAssets<GpuImage>>,
-    clouds_image: Res<CloudsImage>,
+    noise_images: Option<Res<CloudsNoiseImages>>,
+    weather_map: Option<Res<PreparedWeatherMap>>,
+    profiles_image: Option<Res<CloudsProfilesImage>>,
     render_device: Res<RenderDevice>,
 ) {
-    let cloud_render_view = gpu_images.get(&clouds_image.cloud_render_image).unwrap();
-    let cloud_atlas_view = gpu_images.get(&clouds_image.cloud_atlas_image).unwrap();
-    let cloud_worley_view = gpu_images.get(&clouds_image.cloud_worley_image).unwrap();
-    let sky_view = gpu_images.get(&clouds_image.sky_image).unwrap();
+    let Some(noise_images) = noise_images else {
+        return;
+    };
+    let Some(cloud_atlas_view) = gpu_images.get(&noise_images.cloud_atlas_image) else {
+        return;
+    };
+    let Some(cloud_worley_view) = gpu_images.get(&noise_images.cloud_worley_image) else {
+        return;
+    };
+    let Some(cloud_curl_view) = gpu_images.get(&noise_images.cloud_curl_image) else {
+        return;
+    };
+    let Some(weather_map) = weather_map else {
+        return;
+    };
+    let Some(profiles_view) = profiles_image.and_then(|image| gpu_images.get(&image.0)) else {
+        return;
+    };
 
     let bind_group = render_device.create_bind_group(
         None,
-        &pipeline_cache.get_bind_group_layout(&pipeline.texture_bind_group_layout),
-        &BindGroupEntries::sequential((
-            &cloud_render_view.texture_view,
-            &cloud_atlas_view.texture_view,
-            &cloud_worley_view.texture_view,
-            &sky_view.texture_view,
+        &pipeline_cache.get_bind_group_layout(&pipeline.noise_bind_group_layout),
+        &BindGroupEntries::with_indices((
+            (0, &cloud_atlas_view.texture_view),
+            (1, &cloud_worley_view.texture_view),
+            (2, &weather_map.texture_view),
+            (3, &weather_map.sampler),
+            (4, &profiles_view.texture_view),
+            (5, &profiles_view.sampler),
+            (6, &cloud_worley_view.sampler),
+            (7, &cloud_curl_view.texture_view),
+            (8, &cloud_curl_view.sampler),
+        )),
+    );
+    commands.insert_resource(CloudsNoiseBindGroup(bind_group));
+
+    let generating = noise_images
+        .generated
+        .as_ref()
+        .is_some_and(|generated| !generated.load(Ordering::Acquire));
+    if !generating {
+        commands.remove_resource::<CloudsInitBindGroups>();
+        return;
+    }
+
+    // Storage textures are bound a single mip level at a time.
+    let worley = &cloud_worley_view.texture;
+    let worley_mip = |level: u32| {
+        worley.create_view(&TextureViewDescriptor {
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            ..default()
+        })
+    };
+    let noise = render_device.create_bind_group(
+        None,
+        &pipeline_cache.get_bind_group_layout(&pipeline.init_bind_group_layout),
+        &BindGroupEntries::with_indices((
+            (0, &cloud_atlas_view.texture_view),
+            (1, &worley_mip(0)),
+            (3, &cloud_curl_view.texture_view),
         )),
     );
-    commands.insert_resource(CloudsImageBindGroup(bind_group));
+    let worley_mips = (1..worley.mip_level_count())
+        .map(|level| {
+            render_device.create_bind_group(
+                None,
+                &pipeline_cache.get_bind_group_layout(&pipeline.worley_mip_bind_group_layout),
+                &BindGroupEntries::with_indices((
+                    (1, &worley_mip(level)),
+                    (2, &worley_mip(level - 1)),
+                )),
+            )
+        })
+        .collect();
+    commands.insert_resource(CloudsInitBindGroups {
+        noise,
+        worley_size: worley.width(),
+        worley_mips,
+    });
+}
+
+fn prepare_view_images_bind_groups(
+    mut commands: Commands,
+    pipeline: Res<CloudsPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    views: Query<(Entity, &CloudsRenderImages)>,
+    render_device: Res<RenderDevice>,
+) {
+    for (entity, render_images) in &views {
+        // The render images get swapped out when the render resolution changes, in which case
+        // they might not have been uploaded to the GPU yet.
+        let Some(cloud_render_view) = gpu_images.get(&render_images.cloud_render_image) else {
+            continue;
+        };
+        let Some(sky_view) = gpu_images.get(&render_images.sky_image) else {
+            continue;
+        };
+        let Some(cloud_depth_view) = gpu_images.get(&render_images.cloud_depth_image) else {
+            continue;
+        };
+        let Some(cloud_raymarch_view) = gpu_images.get(&render_images.cloud_raymarch_image) else {
+            continue;
+        };
+        let Some(cloud_history_view) = gpu_images.get(&render_images.cloud_history_image) else {
+            continue;
+        };
+        let Some(cloud_history_depth_view) =
+            gpu_images.get(&render_images.cloud_history_depth_image)
+        else {
+            continue;
+        };
+
+        let bind_group = render_device.create_bind_group(
+            None,
+            &pipeline_cache.get_bind_group_layout(&pipeline.images_bind_group_layout),
+            &BindGroupEntries::sequential((
+                &cloud_render_view.texture_view,
+                &sky_view.texture_view,
+                &cloud_depth_view.texture_view,
+                &cloud_raymarch_view.texture_view,
+                &cloud_history_view.texture_view,
+                &cloud_history_depth_view.texture_view,
+            )),
+        );
+        commands.entity(entity).insert(CloudsViewImages {
+            bind_group,
+            render_image: render_images.cloud_render_image.id(),
+            render_size: cloud_render_view.size_2d(),
+            history_copies: [
+                (
+                    cloud_render_view.texture.clone(),
+                    cloud_history_view.texture.clone(),
+                ),
+                (
+                    cloud_depth_view.texture.clone(),
+                    cloud_history_depth_view.texture.clone(),
+                ),
+            ],
+        });
+    }
 }
 
 /// The compute shading pipeline
 ///
 /// Note that the compute shader is loaded in [`CloudsShaderPlugin`] so this resource depends on
-/// that plugin.
+/// that plugin. The `update` entry point reads the depth prepass and the atmosphere of the view and
+/// therefore gets specialized per view, see [`CloudsPipelineKey`].
 #[derive(Resource)]
-struct CloudsPipeline {
-    texture_bind_group_layout: BindGroupLayoutDescriptor,
+pub(crate) struct CloudsPipeline {
+    init_bind_group_layout: BindGroupLayoutDescriptor,
+    worley_mip_bind_group_layout: BindGroupLayoutDescriptor,
+    noise_bind_group_layout: BindGroupLayoutDescriptor,
     uniform_bind_group_layout: BindGroupLayoutDescriptor,
+    images_bind_group_layout: BindGroupLayoutDescriptor,
+    pub environment_map_bind_group_layout: BindGroupLayoutDescriptor,
+    pub shadow_map_bind_group_layout: BindGroupLayoutDescriptor,
+    pub depth_bind_group_layout: BindGroupLayoutDescriptor,
+    pub depth_bind_group_layout_multisampled: BindGroupLayoutDescriptor,
+    pub atmosphere_bind_group_layout: BindGroupLayoutDescriptor,
+    shader: Handle<Shader>,
+    /// The shader defs of the [`CloudsTextureFormats`].
+    shader_defs: Vec<ShaderDefVal>,
     init_pipeline: CachedComputePipelineId,
-    update_pipeline: CachedComputePipelineId,
+    init_worley_pipeline: CachedComputePipelineId,
+    downsample_worley_pipeline: CachedComputePipelineId,
+    pub interpolate_pipeline: CachedComputePipelineId,
+    pub resolve_pipeline: CachedComputePipelineId,
+    pub environment_map_pipeline: CachedComputePipelineId,
+    pub shadow_map_pipeline: CachedComputePipelineId,
 }
 
 impl FromWorld for CloudsPipeline {
     fn from_world(world: &mut World) -> Self {
-        let render_device = world.resource::<RenderDevice>();
-        let texture_bind_group_layout = CloudsImage::bind_group_layout_descriptor(render_device);
+        let formats = *world.resource::<CloudsTextureFormats>();
+        let init_bind_group_layout = BindGroupLayoutDescriptor::new(
+            "clouds_init_bind_group_layout",
+            &BindGroupLayoutEntries::with_indices(
+                ShaderStages::COMPUTE,
+                (
+                    (
+                        0,
+                        texture_storage_2d(ATLAS_FORMAT, StorageTextureAccess::WriteOnly),
+                    ),
+                    (
+                        1,
+                        texture_storage_3d(formats.worley, StorageTextureAccess::WriteOnly),
+                    ),
+                    (
+                        3,
+                        texture_storage_2d(CURL_FORMAT, StorageTextureAccess::WriteOnly),
+                    ),
+                ),
+            ),
+        );
+        let worley_mip_bind_group_layout = BindGroupLayoutDescriptor::new(
+            "clouds_worley_mip_bind_group_layout",
+            &BindGroupLayoutEntries::with_indices(
+                ShaderStages::COMPUTE,
+                (
+                    (
+                        1,
+                        texture_storage_3d(formats.worley, StorageTextureAccess::WriteOnly),
+                    ),
+                    (
+                        2,
+                        texture_3d(TextureSampleType::Float { filterable: false }),
+                    ),
+                ),
+            ),
+        );
+        // The noise textures are only read when rendering, so they can be loaded in any float
+        // format, as long as the Worley noise can be filtered. The weather map and the profiles of
+        // the types of clouds shape the clouds along with the noise textures.
+        let noise_entries = BindGroupLayoutEntries::with_indices(
+            ShaderStages::COMPUTE,
+            (
+                (
+                    0,
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
+                (1, texture_3d(TextureSampleType::Float { filterable: true })),
+                (2, texture_2d(TextureSampleType::Float { filterable: true })),
+                (3, sampler(SamplerBindingType::Filtering)),
+                (4, texture_2d(TextureSampleType::Float { filterable: true })),
+                (5, sampler(SamplerBindingType::Filtering)),
+                (6, sampler(SamplerBindingType::Filtering)),
+                (7, texture_2d(TextureSampleType::Float { filterable: true })),
+                (8, sampler(SamplerBindingType::Filtering)),
+            ),
+        );
+        let noise_bind_group_layout =
+            BindGroupLayoutDescriptor::new("clouds_noise_bind_group_layout", &noise_entries);
+        let render_image = || texture_storage_2d(formats.render, StorageTextureAccess::ReadWrite);
+        let depth_image = || texture_storage_2d(DEPTH_FORMAT, StorageTextureAccess::ReadWrite);
+        // The bindings follow the fields of `CloudsRenderImages`.
+        let images_bind_group_layout = BindGroupLayoutDescriptor::new(
+            "clouds_images_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    render_image(),
+                    render_image(),
+                    depth_image(),
+                    render_image(),
+                    render_image(),
+                    depth_image(),
+                ),
+            ),
+        );
+
+        // The storage textures of the shaders are declared with the formats.
+        let mut noise_shader_defs = Vec::new();
+        if formats.worley == TextureFormat::Rgba8Unorm {
+            noise_shader_defs.push("WORLEY_FORMAT_RGBA8UNORM".into());
+        }
+        let mut shader_defs = Vec::new();
+        if formats.render == TextureFormat::Rgba32Float {
+            shader_defs.push("RENDER_FORMAT_RGBA32FLOAT".into());
+        }
+        let noise_shader = load_embedded_asset!(world, "shaders/clouds_noise.wgsl");
         let shader = load_embedded_asset!(world, "shaders/clouds_compute.wgsl");
         let pipeline_cache = world.resource::<PipelineCache>();
 
         let entries = BindGroupLayoutEntries::sequential(
             ShaderStages::COMPUTE,
-            (uniform_buffer::<CloudsUniform>(false),),
+            (uniform_buffer::<CloudsUniform>(true),),
         );
 
         let uniform_bind_group_layout =
             BindGroupLayoutDescriptor::new("uniform_bind_group_layout", &entries);
+        let depth_bind_group_layout = BindGroupLayoutDescriptor::new(
+            "clouds_depth_bind_group_layout",
+            &BindGroupLayoutEntries::single(ShaderStages::COMPUTE, texture_depth_2d()),
+        );
+        let depth_bind_group_layout_multisampled = BindGroupLayoutDescriptor::new(
+            "clouds_depth_bind_group_layout_multisampled",
+            &BindGroupLayoutEntries::single(ShaderStages::COMPUTE, texture_depth_2d_multisampled()),
+        );
 
-        let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            zero_initialize_workgroup_memory: false,
-            label: None,
-            layout: vec![
-                uniform_bind_group_layout.clone(),
-                texture_bind_group_layout.clone(),
-            ],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Some(Cow::from("init")),
-        });
-        let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            zero_initialize_workgroup_memory: false,
-            label: None,
-            layout: vec![
-                uniform_bind_group_layout.clone(),
-                texture_bind_group_layout.clone(),
-            ],
-            push_constant_ranges: Vec::new(),
-            shader,
-            shader_defs: vec![],
-            entry_point: Some(Cow::from("update")),
-        });
+        let atmosphere_bind_group_layout = BindGroupLayoutDescriptor::new(
+            "clouds_atmosphere_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<CloudsAtmosphereUniform>(true),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                ),
+            ),
+        );
+
+        // Doesn't overlap with the bindings of the view images, which use the same group.
+        let environment_map_bind_group_layout = BindGroupLayoutDescriptor::new(
+            "clouds_environment_map_bind_group_layout",
+            &BindGroupLayoutEntries::with_indices(
+                ShaderStages::COMPUTE,
+                ((
+                    6,
+                    texture_storage_2d(TextureFormat::Rgba16Float, StorageTextureAccess::WriteOnly),
+                ),),
+            ),
+        );
+
+        let shadow_map_bind_group_layout = BindGroupLayoutDescriptor::new(
+            "clouds_shadow_map_bind_group_layout",
+            &BindGroupLayoutEntries::with_indices(
+                ShaderStages::COMPUTE,
+                ((
+                    7,
+                    texture_storage_2d(TextureFormat::Rgba16Float, StorageTextureAccess::WriteOnly),
+                ),),
+            ),
+        );
+
+        let queue_noise_pipeline =
+            |entry_point: &'static str, layout: &BindGroupLayoutDescriptor| {
+                pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                    zero_initialize_workgroup_memory: false,
+                    label: None,
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: Vec::new(),
+                    shader: noise_shader.clone(),
+                    shader_defs: noise_shader_defs.clone(),
+                    entry_point: Some(Cow::from(entry_point)),
+                })
+            };
+        let init_pipeline = queue_noise_pipeline("init", &init_bind_group_layout);
+        let init_worley_pipeline = queue_noise_pipeline("init_worley", &init_bind_group_layout);
+        let downsample_worley_pipeline =
+            queue_noise_pipeline("downsample_worley", &worley_mip_bind_group_layout);
+
+        let queue_pipeline = |entry_point: &'static str, layout: &BindGroupLayoutDescriptor| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                zero_initialize_workgroup_memory: false,
+                label: None,
+                layout: vec![
+                    uniform_bind_group_layout.clone(),
+                    noise_bind_group_layout.clone(),
+                    layout.clone(),
+                ],
+                push_constant_ranges: Vec::new(),
+                shader: shader.clone(),
+                shader_defs: shader_defs.clone(),
+                entry_point: Some(Cow::from(entry_point)),
+            })
+        };
+        let interpolate_pipeline = queue_pipeline("interpolate", &images_bind_group_layout);
+        let resolve_pipeline = queue_pipeline("resolve", &images_bind_group_layout);
+        let environment_map_pipeline =
+            queue_pipeline("environment_map", &environment_map_bind_group_layout);
+        let shadow_map_pipeline = queue_pipeline("shadow_map", &shadow_map_bind_group_layout);
 
         CloudsPipeline {
-            texture_bind_group_layout,
+            init_bind_group_layout,
+            worley_mip_bind_group_layout,
+            noise_bind_group_layout,
             uniform_bind_group_layout,
+            images_bind_group_layout,
+            environment_map_bind_group_layout,
+            shadow_map_bind_group_layout,
+            depth_bind_group_layout,
+            depth_bind_group_layout_multisampled,
+            atmosphere_bind_group_layout,
+            shader,
+            shader_defs,
             init_pipeline,
-            update_pipeline,
+            init_worley_pipeline,
+            downsample_worley_pipeline,
+            interpolate_pipeline,
+            resolve_pipeline,
+            environment_map_pipeline,
+            shadow_map_pipeline,
         }
     }
 }
 
-enum CloudsState {
-    Loading,
-    Init,
-    Update,
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct CloudsPipelineKey {
+    /// Whether the depth prepass of the view is multisampled.
+    pub multisampled: bool,
+    /// Whether the view has Bevy's [`Atmosphere`](bevy::pbr::Atmosphere).
+    pub atmosphere: bool,
 }
 
-struct CloudsNode {
-    state: CloudsState,
-}
+impl SpecializedComputePipeline for CloudsPipeline {
+    type Key = CloudsPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> ComputePipelineDescriptor {
+        let mut shader_defs = self.shader_defs.clone();
+        let depth_bind_group_layout = if key.multisampled {
+            shader_defs.push("MULTISAMPLED".into());
+            self.depth_bind_group_layout_multisampled.clone()
+        } else {
+            self.depth_bind_group_layout.clone()
+        };
+
+        let mut layout = vec![
+            self.uniform_bind_group_layout.clone(),
+            self.noise_bind_group_layout.clone(),
+            self.images_bind_group_layout.clone(),
+            depth_bind_group_layout,
+        ];
+        if key.atmosphere {
+            shader_defs.push("ATMOSPHERE".into());
+            layout.push(self.atmosphere_bind_group_layout.clone());
+        }
 
-impl Default for CloudsNode {
-    fn default() -> Self {
-        Self {
-            state: CloudsState::Loading,
+        ComputePipelineDescriptor {
+            zero_initialize_workgroup_memory: false,
+            label: None,
+            layout,
+            push_constant_ranges: Vec::new(),
+            shader: self.shader.clone(),
+            shader_defs,
+            entry_point: Some(Cow::from("update")),
         }
     }
 }
 
-impl Node for CloudsNode {
+/// Generates the noise textures once, the clouds are rendered per view by
+/// [`CloudsRenderNode`](crate::render::CloudsRenderNode).
+///
+/// Loaded noise textures don't need to be generated, this only makes them ready once loaded.
+#[derive(Default)]
+struct CloudsInitNode {
+    /// The flag of the noise that gets generated this frame.
+    generating: Option<Arc<AtomicBool>>,
+}
+
+impl Node for CloudsInitNode {
     fn update(&mut self, world: &mut World) {
+        // The noise got generated last frame.
+        if let Some(generated) = self.generating.take() {
+            generated.store(true, Ordering::Release);
+        }
+
         let pipeline = world.resource::<CloudsPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
-
-        // if the corresponding pipeline has loaded, transition to the next stage
-        match self.state {
-            CloudsState::Loading => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.init_pipeline)
-                {
-                    self.state = CloudsState::Init;
-                }
-            }
-            CloudsState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
-                {
-                    self.state = CloudsState::Update;
+        let generated = world
+            .get_resource::<CloudsNoiseImages>()
+            .and_then(|noise_images| noise_images.generated.clone());
+
+        let ready = match generated {
+            Some(generated) if !generated.load(Ordering::Acquire) => {
+                let pipelines_ready = [
+                    pipeline.init_pipeline,
+                    pipeline.init_worley_pipeline,
+                    pipeline.downsample_worley_pipeline,
+                ]
+                .into_iter()
+                .all(|id| {
+                    matches!(
+                        pipeline_cache.get_compute_pipeline_state(id),
+                        CachedPipelineState::Ok(_)
+                    )
+                });
+                if pipelines_ready && world.contains_resource::<CloudsInitBindGroups>() {
+                    self.generating = Some(generated);
                 }
+                self.generating.is_some()
             }
-            CloudsState::Update => {}
+            _ => true,
+        };
+        if ready && world.contains_resource::<CloudsNoiseBindGroup>() {
+            world.insert_resource(CloudsNoiseReady);
+        } else {
+            world.remove_resource::<CloudsNoiseReady>();
         }
     }
 
@@ -234,42 +757,44 @@ impl Node for CloudsNode {
         render_context: &mut RenderContext,
         world: &World,
     ) -> Result<(), NodeRunError> {
-        let texture_bind_group = &world.resource::<CloudsImageBindGroup>().0;
-        let uniform_bind_group = &world.resource::<CloudsUniformBindGroup>().0;
+        if self.generating.is_none() {
+            return Ok(());
+        }
+        let bind_groups = world.resource::<CloudsInitBindGroups>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let pipeline = world.resource::<CloudsPipeline>();
+        let (Some(init_pipeline), Some(init_worley_pipeline), Some(downsample_worley_pipeline)) = (
+            pipeline_cache.get_compute_pipeline(pipeline.init_pipeline),
+            pipeline_cache.get_compute_pipeline(pipeline.init_worley_pipeline),
+            pipeline_cache.get_compute_pipeline(pipeline.downsample_worley_pipeline),
+        ) else {
+            return Ok(());
+        };
 
         let mut pass = render_context
             .command_encoder()
             .begin_compute_pass(&ComputePassDescriptor::default());
 
-        pass.set_bind_group(0, uniform_bind_group, &[]);
-        pass.set_bind_group(1, texture_bind_group, &[]);
-
-        match self.state {
-            CloudsState::Loading => {}
-            CloudsState::Init => {
-                let init_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.init_pipeline)
-                    .unwrap();
-                pass.set_pipeline(init_pipeline);
-                pass.dispatch_workgroups(
-                    IMAGE_SIZE / WORKGROUP_SIZE,
-                    IMAGE_SIZE / WORKGROUP_SIZE,
-                    1,
-                );
-            }
-            CloudsState::Update => {
-                let update_pipeline = pipeline_cache
-                    .get_compute_pipeline(pipeline.update_pipeline)
-                    .unwrap();
-                pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(
-                    IMAGE_SIZE / WORKGROUP_SIZE,
-                    IMAGE_SIZE / WORKGROUP_SIZE,
-                    1,
-                );
-            }
+        // The curl noise is generated by the same invocations, it's smaller than the atlas.
+        pass.set_bind_group(0, &bind_groups.noise, &[]);
+        pass.set_pipeline(init_pipeline);
+        pass.dispatch_workgroups(
+            ATLAS_SIZE.div_ceil(WORKGROUP_SIZE),
+            ATLAS_SIZE.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+
+        let worley_groups = bind_groups.worley_size.div_ceil(WORLEY_WORKGROUP_SIZE);
+        pass.set_pipeline(init_worley_pipeline);
+        pass.dispatch_workgroups(worley_groups, worley_groups, worley_groups);
+
+        // Each mip level is half the size of the previous one.
+        pass.set_pipeline(downsample_worley_pipeline);
+        for (level, bind_group) in (1..).zip(&bind_groups.worley_mips) {
+            let size = (bind_groups.worley_size >> level).max(1);
+            let groups = size.div_ceil(WORLEY_WORKGROUP_SIZE);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(groups, groups, groups);
         }
         Ok(())
     }
@@ -279,36 +804,68 @@ impl Node for CloudsNode {
 pub(crate) struct CloudsComputePlugin;
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
-struct CloudsLabel;
+pub(crate) enum CloudsLabel {
+    /// Generates the noise textures, see [`CloudsInitNode`].
+    Init,
+    /// Renders the cubemaps of
+    /// [`CloudsEnvironmentMap`](crate::environment_map::CloudsEnvironmentMap)s, see
+    /// [`CloudsEnvironmentMapNode`](crate::environment_map::CloudsEnvironmentMapNode).
+    EnvironmentMap,
+    /// Renders the shadow map of the clouds, see
+    /// [`CloudsShadowMapNode`](crate::shadows::CloudsShadowMapNode).
+    ShadowMap,
+    /// Renders the clouds of a view, see [`CloudsRenderNode`](crate::render::CloudsRenderNode).
+    Render,
+}
 
 impl Plugin for CloudsComputePlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(ExtractResourcePlugin::<CloudsImage>::default());
-        app.add_plugins(ExtractResourcePlugin::<CloudsUniform>::default());
+        app.add_plugins((
+            ExtractResourcePlugin::<CloudsNoiseImages>::default(),
+            ExtractResourcePlugin::<CloudsProfilesImage>::default(),
+            ExtractResourcePlugin::<CloudsLayers>::default(),
+            ExtractResourcePlugin::<CloudsUniform>::default(),
+            ExtractComponentPlugin::<VolumetricClouds>::default(),
+            ExtractComponentPlugin::<CameraMatrices>::default(),
+            ExtractComponentPlugin::<CloudsRenderImages>::default(),
+        ));
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app.add_systems(
             Render,
-            prepare_textures_bind_group.in_set(RenderSystems::PrepareResources),
-        );
-        render_app.add_systems(
-            Render,
-            prepare_uniforms_bind_group.in_set(RenderSystems::PrepareResources),
+            (
+                prepare_noise_bind_group,
+                prepare_view_images_bind_groups,
+                prepare_uniforms_bind_group,
+            )
+                .chain()
+                .in_set(RenderSystems::PrepareResources),
         );
 
         let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
-        render_graph.add_node(CloudsLabel, CloudsNode::default());
-        render_graph.add_node_edge(CloudsLabel, bevy::render::graph::CameraDriverLabel);
+        render_graph.add_node(CloudsLabel::Init, CloudsInitNode::default());
+        render_graph.add_node_edge(CloudsLabel::Init, bevy::render::graph::CameraDriverLabel);
 
-        render_app.add_systems(
-            ExtractSchedule,
-            (extract_clouds_config, extract_time, extract_camera_matrices),
-        );
+        render_app.add_systems(ExtractSchedule, (extract_clouds_config, extract_time));
     }
 
     fn finish(&self, app: &mut App) {
+        let formats = match (
+            app.world().get_resource::<RenderAdapter>(),
+            app.world().get_resource::<RenderDevice>(),
+        ) {
+            (Some(adapter), Some(device)) => CloudsTextureFormats::new(adapter, device).expect(
+                "The GPU doesn't support `Rgba16Float` or `Rgba32Float` as read-write storage \
+                textures, which the clouds are rendered to",
+            ),
+            _ => CloudsTextureFormats::default(),
+        };
+        app.insert_resource(formats);
+
         let render_app = app.sub_app_mut(RenderApp);
+        render_app.insert_resource(formats);
         render_app.init_resource::<CloudsPipeline>();
+        render_app.init_resource::<SpecializedComputePipelines<CloudsPipeline>>();
         render_app.init_resource::<CloudsUniformBuffer>();
     }
 }
@@ -320,7 +877,3 @@ fn extract_clouds_config(mut commands: Commands, config: Extract<Res<CloudsConfi
 fn extract_time(mut commands: Commands, time: Extract<Res<Time>>) {
     commands.insert_resource(**time);
 }
-
-fn extract_camera_matrices(mut commands: Commands, camera: Extract<Res<CameraMatrices>>) {
-    commands.insert_resource(**camera);
-}
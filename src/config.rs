@@ -1,4 +1,11 @@
-use bevy::prelude::*;
+use bevy::{
+    core_pipeline::prepass::DepthPrepass, prelude::*, render::extract_component::ExtractComponent,
+};
+
+use crate::compute::CameraMatrices;
+
+/// The default [`CloudsConfig::sun_color`], a warm white.
+pub(crate) const DEFAULT_SUN_COLOR: Vec4 = Vec4::new(1.4, 1.26, 1.19, 1.4);
 
 #[derive(Resource, Clone, Copy)]
 /// The configuration that gets passed to the compute shader that renders the clouds.
@@ -28,10 +35,25 @@ pub struct CloudsConfig {
     /// Height of the `clouds_top_height` of the cloud layer.
     pub clouds_top_height: f32,
     /// `clouds_coverage` of 0.0 means no clouds (fair weather), 1.0 means full overcast
+    ///
+    /// Replaced by the coverage of the [`CloudsWeatherMap`](crate::weather::CloudsWeatherMap), if
+    /// there is one.
     pub clouds_coverage: f32,
+    /// Selects the vertical density profile of the clouds from the
+    /// [`CloudsProfiles`](crate::profiles::CloudsProfiles), from 0 for the first profile to 1 for
+    /// the last. By default, 0 gives flat stratus and 1 towering cumulonimbus.
+    ///
+    /// Replaced by the type of the [`CloudsWeatherMap`](crate::weather::CloudsWeatherMap), if
+    /// there is one.
+    pub cloud_type: f32,
     /// Determines how much the base cloud structure is eroded by higher-frequency,
     /// lower-amplitude detail noise.
     pub clouds_detail_strength: f32,
+    /// Distance in meters by which curl noise offsets the detail noise at the bottom of the
+    /// clouds, which turns the erosion into wispy billows. The offset fades out towards the top.
+    pub clouds_curl_strength: f32,
+    /// Determines the scale of the curl noise, relative to `clouds_base_scale`.
+    pub clouds_curl_scale: f32,
     /// Softness of the clouds
     pub clouds_base_edge_softness: f32,
     /// Softness of the `clouds_bottom_height` of the clouds
@@ -49,9 +71,28 @@ pub struct CloudsConfig {
     pub backward_scattering_g: f32,
     /// Factor between 0.0 and 1.0 for mixing forward and backward scattering.
     pub scattering_lerp: f32,
+    /// Number of octaves of the approximation of multiple scattering of the Frostbite paper in
+    /// README, which brightens the inside of thick clouds. 1 only computes single scattering.
+    pub multiple_scattering_octaves: u32,
+    /// Factor by which each octave of multiple scattering scales down the extinction towards the
+    /// light. Should be at most `multiple_scattering_contribution` to conserve energy.
+    pub multiple_scattering_attenuation: f32,
+    /// Factor by which each octave of multiple scattering scales down its contribution.
+    pub multiple_scattering_contribution: f32,
+    /// Factor by which each octave of multiple scattering scales down the eccentricity of the
+    /// scattering lobes, which makes the scattered light less directional.
+    pub multiple_scattering_eccentricity: f32,
+    /// Strength of the "powder" effect of Horizon Zero Dawn, which darkens the thin edges of the
+    /// clouds lit by the sun when looking away from it and gives cumulus dark creases. 0 disables
+    /// it.
+    pub powder_strength: f32,
     /// The color of ambient lighting at the `clouds_top_height` of the clouds.
+    ///
+    /// Cameras with Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) use the sky instead.
     pub clouds_ambient_color_top: Vec4,
     /// The color of ambient lighting at the `clouds_bottom_height` of the clouds.
+    ///
+    /// Cameras with Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) use the ground instead.
     pub clouds_ambient_color_bottom: Vec4,
     /// Minimal transmittance in a ray, if transmittance is too low the ray is discarded.
     pub clouds_min_transmittance: f32,
@@ -59,19 +100,61 @@ pub struct CloudsConfig {
     pub clouds_base_scale: f32,
     ///Determines the scale of the details inside the clouds
     pub clouds_detail_scale: f32,
-    /// Direction towards the sun.
+    /// Direction towards the sun, kept in sync with the light that has [`CloudsSun`].
+    ///
+    /// Cameras with Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) use the direction of that light,
+    /// or else of the brightest [`DirectionalLight`], like the atmosphere does.
     pub sun_dir: Vec4,
-    /// Color of the sun (HDR, RGBA).
+    /// Color of the sun (HDR, RGBA), kept in sync with the light that has [`CloudsSun`].
+    ///
+    /// Cameras with Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) use the color and illuminance of
+    /// that light, or else of the brightest [`DirectionalLight`], attenuated by the atmosphere.
     pub sun_color: Vec4,
+    /// Direction towards the moon, which lights the clouds at night and is drawn in the sky.
+    pub moon_dir: Vec4,
+    /// Color of the moonlight at full moon (HDR, RGBA), in the same units as `sun_color`.
+    ///
+    /// The light is scaled by the phase of the moon, the fraction of the moon that is lit by the
+    /// sun, which follows from `sun_dir` and `moon_dir`. The default is a lot brighter than real
+    /// moonlight, such that the clouds stay visible at night without changing the exposure.
+    /// Setting it to zero skips the extra shadow raymarch of the moonlight.
+    pub moon_color: Vec4,
+    /// Brightness of the stars, which fade out during twilight.
+    ///
+    /// Cameras with Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) don't draw the stars and the moon.
+    pub stars_intensity: f32,
+    /// Intensity of the crepuscular rays, the shafts of sunlight that shine through gaps between
+    /// the clouds. 0 disables them.
+    ///
+    /// The rays are blurred in screen space towards the sun while compositing the clouds, so they
+    /// only show when the sun is in front of the camera. They fade away with the angle from the
+    /// sun and are tinted by `sun_color`.
+    pub crepuscular_rays_intensity: f32,
+    /// Factor by which the light of each sample of the crepuscular rays gets scaled down, the
+    /// further from a pixel towards the sun. Lower values give shorter rays.
+    pub crepuscular_rays_decay: f32,
+    /// Number of samples between a pixel and the sun for the crepuscular rays. More samples give
+    /// smoother rays at a higher cost.
+    pub crepuscular_rays_samples: u32,
     /// Strength of reprojection. 0.0 means we don't mix the current frame with the last frame.
     /// 0.95 means we take 5% of the current frame and 95% of last frame and combine those two to
     /// reduce noise.
+    /// With [`Interleaving`], each pixel only gets a fresh sample once per tile, so the weight of
+    /// that sample is scaled up by the number of pixels of a tile to converge just as fast.
     /// Automatically updates each frame.
     pub reprojection_strength: f32,
     /// Determines whether the egui UI is visible or not. Requires the `debug` feature.
     pub ui_visible: bool,
-    /// Resolution of the image we're writing to.
-    pub render_resolution: Vec2,
+    /// Fraction of the resolution of the camera's viewport at which the clouds are raymarched.
+    ///
+    /// Rendering at a lower resolution is a lot cheaper, the result is upsampled to the full
+    /// resolution using an edge-aware filter.
+    pub render_scale: RenderScale,
+    /// Raymarch only a fraction of the pixels each frame, following the method of Horizon Zero
+    /// Dawn. The other pixels reuse the result of previous frames.
+    ///
+    /// This divides the cost of raymarching by up to 16, which allows for more raymarching steps.
+    pub interleaving: Interleaving,
     /// Velocity of the wind.
     pub wind_velocity: Vec3,
 }
@@ -86,7 +169,11 @@ impl Default for CloudsConfig {
             clouds_bottom_height: 1250.0,
             clouds_top_height: 2400.0,
             clouds_coverage: 0.5,
+            // Cumulus in the default profiles.
+            cloud_type: 2.0 / 3.0,
             clouds_detail_strength: 0.27,
+            clouds_curl_strength: 30.0,
+            clouds_curl_scale: 8.0,
             clouds_base_edge_softness: 0.1,
             clouds_bottom_softness: 0.25,
             clouds_density: 0.03,
@@ -95,17 +182,141 @@ impl Default for CloudsConfig {
             forward_scattering_g: 0.8,
             backward_scattering_g: -0.2,
             scattering_lerp: 0.5,
+            multiple_scattering_octaves: 3,
+            multiple_scattering_attenuation: 0.5,
+            multiple_scattering_contribution: 0.5,
+            multiple_scattering_eccentricity: 0.5,
+            powder_strength: 0.0,
             clouds_ambient_color_top: Vec4::new(149.0, 167.0, 200.0, 0.0) * (1.5 / 225.0),
             clouds_ambient_color_bottom: Vec4::new(39.0, 67.0, 87.0, 0.0) * (1.5 / 225.0),
             clouds_min_transmittance: 0.1,
             clouds_base_scale: 1.5,
             clouds_detail_scale: 42.0,
             sun_dir: Vec4::new(sun_dir.x, sun_dir.y, sun_dir.z, 0.0),
-            sun_color: Vec4::new(1.0, 0.9, 0.85, 1.0) * 1.4,
+            sun_color: DEFAULT_SUN_COLOR,
+            // A full moon opposite the sun.
+            moon_dir: Vec4::new(-sun_dir.x, -sun_dir.y, -sun_dir.z, 0.0),
+            moon_color: Vec4::new(0.6, 0.7, 0.9, 1.0) * 0.1,
+            stars_intensity: 1.0,
+            crepuscular_rays_intensity: 0.0,
+            crepuscular_rays_decay: 0.95,
+            crepuscular_rays_samples: 32,
             reprojection_strength: 0.95,
             ui_visible: true,
-            render_resolution: Vec2::new(1920.0, 1080.0),
+            render_scale: RenderScale::Full,
+            interleaving: Interleaving::None,
             wind_velocity: Vec3::new(-1.1, 0.0, 2.3),
         }
     }
 }
+
+/// Marks a 3D camera that renders the clouds, with optional overrides of [`CloudsConfig`] for
+/// this camera only.
+///
+/// Each camera with this component gets its own render targets and history, and a
+/// [`DepthPrepass`] such that the clouds are occluded by the scene.
+///
+/// # Example
+///
+/// ```rust ignore
+/// commands.spawn((
+///     Camera3d::default(),
+///     VolumetricClouds {
+///         render_scale: Some(RenderScale::Half),
+///         ..default()
+///     },
+/// ));
+/// ```
+#[derive(Component, Clone, Copy, Debug, Default, ExtractComponent)]
+#[require(DepthPrepass, CameraMatrices)]
+pub struct VolumetricClouds {
+    /// Overrides [`CloudsConfig::clouds_raymarch_steps_count`].
+    pub clouds_raymarch_steps_count: Option<u32>,
+    /// Overrides [`CloudsConfig::clouds_shadow_raymarch_steps_count`].
+    pub clouds_shadow_raymarch_steps_count: Option<u32>,
+    /// Overrides [`CloudsConfig::render_scale`].
+    pub render_scale: Option<RenderScale>,
+    /// Overrides [`CloudsConfig::interleaving`].
+    pub interleaving: Option<Interleaving>,
+}
+
+impl VolumetricClouds {
+    /// The configuration of the clouds of this camera.
+    pub(crate) fn apply_to(&self, config: CloudsConfig) -> CloudsConfig {
+        CloudsConfig {
+            clouds_raymarch_steps_count: self
+                .clouds_raymarch_steps_count
+                .unwrap_or(config.clouds_raymarch_steps_count),
+            clouds_shadow_raymarch_steps_count: self
+                .clouds_shadow_raymarch_steps_count
+                .unwrap_or(config.clouds_shadow_raymarch_steps_count),
+            render_scale: self.render_scale.unwrap_or(config.render_scale),
+            interleaving: self.interleaving.unwrap_or(config.interleaving),
+            ..config
+        }
+    }
+}
+
+/// The resolution at which the clouds are raymarched, relative to the resolution of the camera's
+/// viewport.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderScale {
+    /// Raymarch every pixel.
+    #[default]
+    Full,
+    /// Raymarch at half the width and height.
+    Half,
+    /// Raymarch at a third of the width and height.
+    Third,
+    /// Raymarch at a quarter of the width and height.
+    Quarter,
+}
+
+impl RenderScale {
+    /// The factor by which the width and height of the render resolution are divided.
+    pub fn divisor(self) -> u32 {
+        match self {
+            RenderScale::Full => 1,
+            RenderScale::Half => 2,
+            RenderScale::Third => 3,
+            RenderScale::Quarter => 4,
+        }
+    }
+
+    /// Size of the images the clouds are raymarched into for a viewport of the given size, which
+    /// is divided by the [`Self::divisor`] (rounded up).
+    pub(crate) fn image_size(self, viewport_size: UVec2) -> UVec2 {
+        let divisor = UVec2::splat(self.divisor());
+        (viewport_size.max(UVec2::ONE) + divisor - 1) / divisor
+    }
+}
+
+/// Determines which pixels are raymarched each frame.
+///
+/// The pixels are divided into tiles and each frame one pixel per tile is raymarched, in the order
+/// of a Bayer matrix. The other pixels keep their result from previous frames, or are interpolated
+/// from the pixels raymarched in the current frame when the camera moved.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interleaving {
+    /// Raymarch every pixel each frame.
+    #[default]
+    None,
+    /// Raymarch 1 of 2 pixels each frame, in a checkerboard pattern.
+    Checkerboard,
+    /// Raymarch 1 of 4 pixels each frame, using a 2x2 Bayer matrix.
+    Bayer2x2,
+    /// Raymarch 1 of 16 pixels each frame, using a 4x4 Bayer matrix.
+    Bayer4x4,
+}
+
+impl Interleaving {
+    /// The size of the tiles of which one pixel is raymarched each frame.
+    pub fn tile_size(self) -> UVec2 {
+        match self {
+            Interleaving::None => UVec2::ONE,
+            Interleaving::Checkerboard => UVec2::new(2, 1),
+            Interleaving::Bayer2x2 => UVec2::splat(2),
+            Interleaving::Bayer4x4 => UVec2::splat(4),
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use core::time::Duration;
+
+use bevy::{
+    ecs::query::QueryState,
+    prelude::*,
+    render::{
+        Extract, ExtractSchedule, Render, RenderApp, RenderSystems,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext},
+        render_resource::{
+            BindGroup, BindGroupEntries, ComputePassDescriptor, PipelineCache,
+            TextureViewDescriptor, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice},
+        sync_component::SyncComponentPlugin,
+        sync_world::RenderEntity,
+        texture::GpuImage,
+    },
+};
+
+use crate::{
+    compute::{
+        CloudsLabel, CloudsNoiseBindGroup, CloudsNoiseReady, CloudsPipeline,
+        CloudsUniformBindGroup, WORKGROUP_SIZE, prepare_uniforms_bind_group,
+    },
+    images::build_environment_map_image,
+};
+
+/// Renders the sky and the clouds as seen from the position of this entity into a cubemap, which
+/// can be used by a [`Skybox`] and a [`GeneratedEnvironmentMapLight`] such that reflections and
+/// ambient lighting follow the clouds.
+///
+/// The cubemap is in the same units as the clouds that are composited over the cameras, which
+/// don't take the [`Exposure`] of the camera into account. Use a brightness or intensity of
+/// `exposure.exposure().recip()` to match them.
+///
+/// # Example
+///
+/// ```rust ignore
+/// let environment_map = CloudsEnvironmentMap::new(&mut images, 256);
+/// commands.spawn((
+///     Camera3d::default(),
+///     VolumetricClouds::default(),
+///     GeneratedEnvironmentMapLight {
+///         environment_map: environment_map.image.clone(),
+///         intensity: Exposure::default().exposure().recip(),
+///         ..default()
+///     },
+///     environment_map,
+/// ));
+/// ```
+///
+/// [`Skybox`]: bevy::core_pipeline::Skybox
+/// [`GeneratedEnvironmentMapLight`]: bevy::light::GeneratedEnvironmentMapLight
+/// [`Exposure`]: bevy::camera::Exposure
+#[derive(Component, Clone, Debug)]
+#[require(Transform)]
+pub struct CloudsEnvironmentMap {
+    /// The cubemap the sky and clouds are rendered to, see [`CloudsEnvironmentMap::new`].
+    pub image: Handle<Image>,
+    /// When the cubemap gets rendered.
+    pub update: EnvironmentMapUpdate,
+    /// Number of faces of the cubemap that are rendered per frame, between 1 and 6. Rendering
+    /// fewer faces spreads the cost of updating the cubemap over multiple frames.
+    pub faces_per_frame: u32,
+}
+
+impl CloudsEnvironmentMap {
+    /// Creates a cubemap with faces of `size` by `size` pixels. The size has to be a power of two
+    /// to use the cubemap for a [`GeneratedEnvironmentMapLight`].
+    ///
+    /// [`GeneratedEnvironmentMapLight`]: bevy::light::GeneratedEnvironmentMapLight
+    pub fn new(images: &mut Assets<Image>, size: u32) -> Self {
+        Self {
+            image: build_environment_map_image(images, size),
+            update: EnvironmentMapUpdate::default(),
+            faces_per_frame: 6,
+        }
+    }
+}
+
+/// Determines when the cubemap of a [`CloudsEnvironmentMap`] gets rendered.
+///
+/// The cubemap is always rendered when the [`CloudsEnvironmentMap`] is added or changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EnvironmentMapUpdate {
+    /// Render [`CloudsEnvironmentMap::faces_per_frame`] faces every frame.
+    #[default]
+    Continuous,
+    /// Render all faces once every interval.
+    Interval(Duration),
+    /// Only render the faces when the [`CloudsEnvironmentMap`] is changed, for example by calling
+    /// `set_changed` on it.
+    OnChange,
+}
+
+/// The direction each face of a cubemap looks at and its up direction, in the order of the layers
+/// of the cubemap. Cubemaps are left-handed, so the faces of +Z and -Z look at -Z and +Z.
+const FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::Z),
+    (Vec3::NEG_Y, Vec3::NEG_Z),
+    (Vec3::NEG_Z, Vec3::Y),
+    (Vec3::Z, Vec3::Y),
+];
+
+/// The inverse view matrix of a face of a cubemap at `origin`.
+///
+/// Using it with an identity inverse projection matrix gives the 90° field of view of a face.
+pub(crate) fn face_inverse_view(origin: Vec3, face: u32) -> Mat4 {
+    let (forward, up) = FACES[face as usize];
+    Transform::from_translation(origin)
+        .looking_to(forward, up)
+        .to_matrix()
+}
+
+/// A [`CloudsEnvironmentMap`] in the render world.
+#[derive(Component)]
+struct ExtractedEnvironmentMap {
+    image: AssetId<Image>,
+    origin: Vec3,
+    faces_per_frame: u32,
+    /// Whether all faces should be rendered (again).
+    refresh: bool,
+}
+
+fn extract_environment_maps(
+    mut commands: Commands,
+    time: Extract<Res<Time>>,
+    environment_maps: Extract<Query<(RenderEntity, Ref<CloudsEnvironmentMap>, &GlobalTransform)>>,
+) {
+    for (entity, environment_map, transform) in &environment_maps {
+        let refresh = environment_map.is_changed()
+            || match environment_map.update {
+                EnvironmentMapUpdate::Continuous => true,
+                EnvironmentMapUpdate::Interval(interval) => {
+                    // Whether a multiple of the interval passed during the last frame.
+                    let interval = interval.as_secs_f32();
+                    let elapsed = time.elapsed_secs();
+                    interval <= 0.0
+                        || (elapsed / interval).floor()
+                            != ((elapsed - time.delta_secs()) / interval).floor()
+                }
+                EnvironmentMapUpdate::OnChange => false,
+            };
+
+        commands.entity(entity).insert(ExtractedEnvironmentMap {
+            image: environment_map.image.id(),
+            origin: transform.translation(),
+            faces_per_frame: environment_map.faces_per_frame.clamp(1, 6),
+            refresh,
+        });
+    }
+}
+
+/// The faces of a cubemap that still have to be rendered.
+#[derive(Component, Clone, Copy, Default)]
+struct EnvironmentMapProgress {
+    next_face: u32,
+    remaining_faces: u32,
+}
+
+/// A face of a cubemap that gets rendered this frame.
+pub(crate) struct EnvironmentMapFace {
+    pub index: u32,
+    /// Offset of the uniforms of the face in
+    /// [`CloudsUniformBuffer`](crate::uniforms::CloudsUniformBuffer).
+    pub uniform_offset: u32,
+}
+
+/// The faces of a cubemap that get rendered this frame.
+#[derive(Component)]
+pub(crate) struct EnvironmentMapFaces {
+    pub origin: Vec3,
+    pub size: u32,
+    pub faces: Vec<EnvironmentMapFace>,
+}
+
+/// Select the faces of each cubemap that get rendered this frame.
+///
+/// Requested faces are kept until the noise textures are generated and the pipeline is compiled,
+/// such that cubemaps which are only rendered once don't end up empty.
+fn prepare_environment_map_faces(
+    mut commands: Commands,
+    pipeline: Res<CloudsPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    noise_ready: Option<Res<CloudsNoiseReady>>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    environment_maps: Query<(
+        Entity,
+        &ExtractedEnvironmentMap,
+        Option<&EnvironmentMapProgress>,
+    )>,
+) {
+    let ready = noise_ready.is_some()
+        && pipeline_cache
+            .get_compute_pipeline(pipeline.environment_map_pipeline)
+            .is_some();
+
+    for (entity, environment_map, progress) in &environment_maps {
+        let mut progress = progress.copied().unwrap_or_default();
+        if environment_map.refresh {
+            progress.remaining_faces = 6;
+        }
+
+        if ready && let Some(image) = gpu_images.get(environment_map.image) {
+            let count = progress
+                .remaining_faces
+                .min(environment_map.faces_per_frame);
+            let faces = (0..count)
+                .map(|i| EnvironmentMapFace {
+                    index: (progress.next_face + i) % 6,
+                    // Set by `prepare_uniforms_bind_group`.
+                    uniform_offset: 0,
+                })
+                .collect();
+            progress.next_face = (progress.next_face + count) % 6;
+            progress.remaining_faces -= count;
+
+            commands.entity(entity).insert(EnvironmentMapFaces {
+                origin: environment_map.origin,
+                size: image.size.width,
+                faces,
+            });
+        } else {
+            commands.entity(entity).remove::<EnvironmentMapFaces>();
+        }
+        commands.entity(entity).insert(progress);
+    }
+}
+
+/// The bind groups of the faces of a cubemap that get rendered this frame, with the offset of
+/// their uniforms.
+#[derive(Component)]
+struct EnvironmentMapBindGroups(Vec<(BindGroup, u32)>);
+
+fn prepare_environment_map_bind_groups(
+    mut commands: Commands,
+    pipeline: Res<CloudsPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    render_device: Res<RenderDevice>,
+    environment_maps: Query<(Entity, &ExtractedEnvironmentMap, &EnvironmentMapFaces)>,
+) {
+    for (entity, environment_map, faces) in &environment_maps {
+        let Some(image) = gpu_images.get(environment_map.image) else {
+            continue;
+        };
+        let layout =
+            pipeline_cache.get_bind_group_layout(&pipeline.environment_map_bind_group_layout);
+        let bind_groups = faces
+            .faces
+            .iter()
+            .map(|face| {
+                // The compute shader writes to a single face at a time.
+                let face_view = image.texture.create_view(&TextureViewDescriptor {
+                    label: Some("clouds_environment_map_face"),
+                    dimension: Some(TextureViewDimension::D2),
+                    base_array_layer: face.index,
+                    array_layer_count: Some(1),
+                    mip_level_count: Some(1),
+                    ..default()
+                });
+                let bind_group = render_device.create_bind_group(
+                    "clouds_environment_map_bind_group",
+                    &layout,
+                    &BindGroupEntries::with_indices(((6, &face_view),)),
+                );
+                (bind_group, face.uniform_offset)
+            })
+            .collect();
+        commands
+            .entity(entity)
+            .insert(EnvironmentMapBindGroups(bind_groups));
+    }
+}
+
+/// Renders the faces of the cubemaps of all [`CloudsEnvironmentMap`]s that are due this frame,
+/// before the cameras that use them are rendered.
+pub(crate) struct CloudsEnvironmentMapNode {
+    environment_maps: QueryState<(
+        &'static EnvironmentMapFaces,
+        &'static EnvironmentMapBindGroups,
+    )>,
+}
+
+impl FromWorld for CloudsEnvironmentMapNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            environment_maps: world.query(),
+        }
+    }
+}
+
+impl Node for CloudsEnvironmentMapNode {
+    fn update(&mut self, world: &mut World) {
+        self.environment_maps.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let Some(noise_bind_group) = world.get_resource::<CloudsNoiseBindGroup>() else {
+            return Ok(());
+        };
+        let Some(uniform_bind_group) = world.get_resource::<CloudsUniformBindGroup>() else {
+            return Ok(());
+        };
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let Some(environment_map_pipeline) = pipeline_cache
+            .get_compute_pipeline(world.resource::<CloudsPipeline>().environment_map_pipeline)
+        else {
+            return Ok(());
+        };
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(environment_map_pipeline);
+        pass.set_bind_group(1, &noise_bind_group.0, &[]);
+
+        for (faces, bind_groups) in self.environment_maps.iter_manual(world) {
+            let workgroups = faces.size.div_ceil(WORKGROUP_SIZE);
+            for (bind_group, uniform_offset) in &bind_groups.0 {
+                pass.set_bind_group(0, &uniform_bind_group.0, &[*uniform_offset]);
+                pass.set_bind_group(2, bind_group, &[]);
+                pass.dispatch_workgroups(workgroups, workgroups, 1);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A plugin which renders the cubemaps of [`CloudsEnvironmentMap`]s.
+pub(crate) struct CloudsEnvironmentMapPlugin;
+
+impl Plugin for CloudsEnvironmentMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(SyncComponentPlugin::<CloudsEnvironmentMap>::default());
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_systems(ExtractSchedule, extract_environment_maps)
+            .add_systems(
+                Render,
+                (
+                    prepare_environment_map_faces
+                        .in_set(RenderSystems::PrepareResources)
+                        .before(prepare_uniforms_bind_group),
+                    prepare_environment_map_bind_groups.in_set(RenderSystems::PrepareBindGroups),
+                ),
+            );
+
+        let node = CloudsEnvironmentMapNode::from_world(render_app.world_mut());
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(CloudsLabel::EnvironmentMap, node);
+        render_graph.add_node_edge(CloudsLabel::Init, CloudsLabel::EnvironmentMap);
+        render_graph.add_node_edge(
+            CloudsLabel::EnvironmentMap,
+            bevy::render::graph::CameraDriverLabel,
+        );
+    }
+}
@@ -1,74 +1,294 @@
+use std::sync::Arc;
+
 use bevy::{
     asset::RenderAssetUsages,
+    image::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor, TextureFormatPixelInfo},
     prelude::*,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    render::{
+        render_resource::{
+            Extent3d, TextureDimension, TextureFormat, TextureFormatFeatureFlags,
+            TextureFormatFeatures, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+            WgpuFeatures,
+        },
+        renderer::{RenderAdapter, RenderDevice},
+    },
+};
+
+use crate::{
+    profiles::CloudProfile,
+    uniforms::{CloudsNoiseImages, CloudsRenderImages},
 };
 
-pub const IMAGE_SIZE: u32 = 1920;
+/// Width and height of the (square) cloud atlas, which does not depend on the render resolution.
+pub const ATLAS_SIZE: u32 = 1920;
+
+/// Format of the generated cloud atlas, which every GPU supports as a storage texture.
+pub const ATLAS_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+/// The formats of the textures of the clouds which are picked based on the capabilities of the GPU.
+///
+/// Compact formats are used where the GPU supports writing them from the compute shaders, otherwise
+/// the clouds fall back to wider formats. The render textures are read and written by the same
+/// pass, which neither `Rgba16Float` nor `Rgba32Float` is guaranteed to support, so the clouds can't
+/// be rendered on GPUs that support neither.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CloudsTextureFormats {
+    /// Format of the generated 3D Worley noise, which only uses the red channel and needs to be
+    /// filterable.
+    pub worley: TextureFormat,
+    /// Format of the textures the clouds and the sky of a camera get rendered to, including the
+    /// history of the clouds.
+    pub render: TextureFormat,
+}
+
+impl Default for CloudsTextureFormats {
+    /// The fallback formats.
+    fn default() -> Self {
+        Self {
+            worley: TextureFormat::Rgba8Unorm,
+            render: TextureFormat::Rgba32Float,
+        }
+    }
+}
+
+impl CloudsTextureFormats {
+    /// Picks the compact formats if the GPU supports them as storage textures with the access the
+    /// compute shaders need.
+    ///
+    /// Returns `None` if the GPU supports none of the formats of the render textures as read-write
+    /// storage textures.
+    pub fn new(adapter: &RenderAdapter, device: &RenderDevice) -> Option<Self> {
+        let features = |format: TextureFormat| -> TextureFormatFeatures {
+            // Without this feature, only the guaranteed features of a format can be used.
+            if device
+                .features()
+                .contains(WgpuFeatures::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+            {
+                adapter.get_texture_format_features(format)
+            } else {
+                format.guaranteed_format_features(device.features())
+            }
+        };
+        let supports = |format: TextureFormat, access: TextureFormatFeatureFlags| {
+            let features = features(format);
+            features
+                .allowed_usages
+                .contains(TextureUsages::STORAGE_BINDING)
+                && features.flags.contains(access)
+        };
+
+        let fallback = Self::default();
+        // Every GPU supports writing the fallback of the Worley noise.
+        let worley = if supports(
+            TextureFormat::R8Unorm,
+            TextureFormatFeatureFlags::STORAGE_WRITE_ONLY,
+        ) {
+            TextureFormat::R8Unorm
+        } else {
+            fallback.worley
+        };
+        let render = [TextureFormat::Rgba16Float, fallback.render]
+            .into_iter()
+            .find(|format| supports(*format, TextureFormatFeatureFlags::STORAGE_READ_WRITE))?;
+        Some(Self { worley, render })
+    }
+}
+
+/// Width and height of the (square) curl noise, which distorts the details of the clouds.
+pub const CURL_SIZE: u32 = 128;
+
+/// Format of the generated curl noise, which every GPU supports as a storage texture.
+pub const CURL_FORMAT: TextureFormat = TextureFormat::Rgba8Unorm;
+
+/// The sampler of the noise textures that are filtered, the 3D Worley noise and the curl noise. It
+/// repeats and filters trilinearly between mip levels.
+pub fn noise_sampler() -> ImageSampler {
+    ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        address_mode_w: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    })
+}
+
+/// Build the noise textures that are generated by the `init` and `init_worley` entry points of the
+/// noise shader: the cloud atlas, the curl noise and the 3D Worley noise of `worley_size` cubed,
+/// with a full chain of mip levels.
+pub fn build_noise_images(
+    images: &mut Assets<Image>,
+    worley_size: u32,
+    formats: &CloudsTextureFormats,
+) -> CloudsNoiseImages {
+    let build_image = |size: Extent3d, dimension: TextureDimension, format: TextureFormat| {
+        let mut image = Image::new_uninit(size, dimension, format, RenderAssetUsages::RENDER_WORLD);
+        // The noise is read back to export it.
+        image.texture_descriptor.usage = TextureUsages::COPY_SRC
+            | TextureUsages::COPY_DST
+            | TextureUsages::STORAGE_BINDING
+            | TextureUsages::TEXTURE_BINDING;
+        image
+    };
 
-pub fn build_images(
-    mut images: ResMut<Assets<Image>>,
-) -> (Handle<Image>, Handle<Image>, Handle<Image>, Handle<Image>) {
-    let mut cloud_render_image = Image::new_fill(
+    let atlas = build_image(
         Extent3d {
-            width: 1920,
-            height: 1080,
+            width: ATLAS_SIZE,
+            height: ATLAS_SIZE,
             depth_or_array_layers: 1,
         },
         TextureDimension::D2,
-        &[0; 4 * 4 * 2],
-        TextureFormat::Rgba32Float,
-        RenderAssetUsages::RENDER_WORLD,
+        ATLAS_FORMAT,
     );
-    cloud_render_image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-
-    let mut cloud_atlas_image = Image::new_fill(
+    let mut worley = build_image(
+        Extent3d {
+            width: worley_size,
+            height: worley_size,
+            depth_or_array_layers: worley_size,
+        },
+        TextureDimension::D3,
+        formats.worley,
+    );
+    worley.texture_descriptor.mip_level_count = worley_size.ilog2() + 1;
+    worley.sampler = noise_sampler();
+    let mut curl = build_image(
         Extent3d {
-            width: IMAGE_SIZE,
-            height: IMAGE_SIZE,
+            width: CURL_SIZE,
+            height: CURL_SIZE,
             depth_or_array_layers: 1,
         },
         TextureDimension::D2,
-        &[0; 4 * 4 * 2],
-        TextureFormat::Rgba32Float,
-        RenderAssetUsages::RENDER_WORLD,
+        CURL_FORMAT,
     );
-    cloud_atlas_image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    curl.sampler = noise_sampler();
+
+    CloudsNoiseImages {
+        cloud_atlas_image: images.add(atlas),
+        cloud_worley_image: images.add(worley),
+        cloud_curl_image: images.add(curl),
+        generated: Some(Arc::default()),
+    }
+}
+
+/// Format of the distance to the clouds of a camera, which needs full precision for reprojection.
+pub const DEPTH_FORMAT: TextureFormat = TextureFormat::R32Float;
+
+/// Build the textures the clouds and sky of a camera get rendered to every frame.
+///
+/// These need to be rebuilt whenever the render resolution changes, see `update_render_images`.
+pub fn build_render_images(
+    images: &mut Assets<Image>,
+    size: UVec2,
+    formats: &CloudsTextureFormats,
+) -> CloudsRenderImages {
+    let size = Extent3d {
+        width: size.x.max(1),
+        height: size.y.max(1),
+        depth_or_array_layers: 1,
+    };
+
+    let mut build_image = |format: TextureFormat| {
+        let pixel = vec![0; format.pixel_size().expect("the format is uncompressed")];
+        let mut image = Image::new_fill(
+            size,
+            TextureDimension::D2,
+            &pixel,
+            format,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+        image.texture_descriptor.usage = TextureUsages::COPY_SRC
+            | TextureUsages::COPY_DST
+            | TextureUsages::STORAGE_BINDING
+            | TextureUsages::TEXTURE_BINDING;
+        images.add(image)
+    };
+
+    CloudsRenderImages {
+        cloud_render_image: build_image(formats.render),
+        cloud_raymarch_image: build_image(formats.render),
+        cloud_depth_image: build_image(DEPTH_FORMAT),
+        cloud_history_image: build_image(formats.render),
+        cloud_history_depth_image: build_image(DEPTH_FORMAT),
+        sky_image: build_image(formats.render),
+    }
+}
 
-    let mut cloud_worley_image = Image::new_fill(
+/// Build the cubemap the sky and clouds get rendered to for a
+/// [`CloudsEnvironmentMap`](crate::environment_map::CloudsEnvironmentMap), with faces of `size` by `size`
+/// pixels.
+pub fn build_environment_map_image(images: &mut Assets<Image>, size: u32) -> Handle<Image> {
+    let mut image = Image::new_fill(
         Extent3d {
-            width: 32,
-            height: 32,
-            depth_or_array_layers: 32,
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
         },
-        TextureDimension::D3,
-        &[0; 4 * 4 * 2],
-        TextureFormat::Rgba32Float,
+        TextureDimension::D2,
+        &[0; 4 * 2],
+        TextureFormat::Rgba16Float,
         RenderAssetUsages::RENDER_WORLD,
     );
-    cloud_worley_image.texture_descriptor.usage =
+    image.texture_descriptor.usage =
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::Cube),
+        ..default()
+    });
+    images.add(image)
+}
 
-    let mut sky_image = Image::new_fill(
+/// Build the image the transmittance of the clouds gets rendered to for a
+/// [`CloudsShadowMap`](crate::shadows::CloudsShadowMap), of `size` by `size` pixels. It starts out
+/// without shadows.
+pub fn build_shadow_map_image(images: &mut Assets<Image>, size: u32) -> Handle<Image> {
+    let mut image = Image::new_fill(
         Extent3d {
-            width: 1920,
-            height: 1080,
+            width: size,
+            height: size,
             depth_or_array_layers: 1,
         },
         TextureDimension::D2,
-        &[0; 4 * 4 * 2],
-        TextureFormat::Rgba32Float,
+        // 1.0 in half precision.
+        &[0x00, 0x3c, 0x00, 0x3c, 0x00, 0x3c, 0x00, 0x3c],
+        TextureFormat::Rgba16Float,
         RenderAssetUsages::RENDER_WORLD,
     );
-    sky_image.texture_descriptor.usage =
+    image.texture_descriptor.usage =
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    images.add(image)
+}
+
+/// Number of heights the profiles of [`CloudsProfiles`](crate::profiles::CloudsProfiles) are
+/// sampled at.
+pub const PROFILE_RESOLUTION: u32 = 64;
 
-    (
-        images.add(cloud_render_image),
-        images.add(cloud_atlas_image),
-        images.add(cloud_worley_image),
-        images.add(sky_image),
-    )
+/// Build the lookup table of the vertical density profiles of the types of clouds, with a row of
+/// [`PROFILE_RESOLUTION`] heights per profile. Without profiles, the density is 1 at all heights.
+///
+/// Unlike the other images, this one is returned so that it can replace the previous table when the
+/// profiles change.
+pub fn build_profiles_image(profiles: &[CloudProfile]) -> Image {
+    let rows = profiles.len().max(1) as u32;
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: PROFILE_RESOLUTION,
+            height: rows,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        // 1.0 in half precision.
+        &[0x00, 0x3c],
+        TextureFormat::R16Float,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::linear();
+    for (row, profile) in profiles.iter().enumerate() {
+        for x in 0..PROFILE_RESOLUTION {
+            let height = x as f32 / (PROFILE_RESOLUTION - 1) as f32;
+            // The single channel holds the luminance of the color, which is the density for gray.
+            let density = profile.density(height);
+            image
+                .set_color_at(x, row as u32, Color::linear_rgb(density, density, density))
+                .expect("the pixel lies within the image");
+        }
+    }
+    image
 }
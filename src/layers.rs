@@ -0,0 +1,126 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+/// Maximum number of [`CloudsLayers`] that get rendered, the main layer of
+/// [`CloudsConfig`](crate::config::CloudsConfig) not included.
+pub const MAX_CLOUD_LAYERS: usize = 4;
+
+/// How a [`CloudLayer`] gets rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CloudLayerMode {
+    /// Raymarch through the layer like the main layer, with the same number of steps. This is as
+    /// expensive as the main layer.
+    #[default]
+    Volumetric,
+    /// Render the layer as a thin sheet at `bottom_height` with a single sample of a 2D texture,
+    /// which is a lot cheaper. The clouds are stretched into streaks, like cirrus.
+    Flat,
+}
+
+/// A layer of clouds in addition to the main layer of [`CloudsConfig`].
+///
+/// The parameters of the clouds that aren't part of a layer, such as the lighting, the erosion by
+/// detail noise and the number of raymarching steps, are shared with the main layer.
+///
+/// [`CloudsConfig`]: crate::config::CloudsConfig
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CloudLayer {
+    /// How the layer gets rendered.
+    pub mode: CloudLayerMode,
+    /// Height of the bottom of the layer.
+    pub bottom_height: f32,
+    /// Height of the top of the layer. A [`CloudLayerMode::Flat`] layer is drawn at
+    /// `bottom_height`, this only determines how thick the clouds are.
+    pub top_height: f32,
+    /// 0.0 means no clouds, 1.0 means full overcast.
+    pub coverage: f32,
+    /// Density of the clouds.
+    pub density: f32,
+    /// Determines the overall scale of the clouds.
+    pub base_scale: f32,
+    /// Determines the scale of the details inside the clouds.
+    pub detail_scale: f32,
+    /// Selects the vertical density profile of the clouds from the
+    /// [`CloudsProfiles`](crate::profiles::CloudsProfiles), unused by flat layers.
+    pub cloud_type: f32,
+    /// Velocity of the wind at the height of the layer.
+    pub wind_velocity: Vec3,
+    /// Whether the [`CloudsWeatherMap`](crate::weather::CloudsWeatherMap) replaces `coverage` and
+    /// `cloud_type` of a volumetric layer, like it does for the main layer.
+    pub use_weather_map: bool,
+}
+
+impl Default for CloudLayer {
+    fn default() -> Self {
+        Self::altocumulus()
+    }
+}
+
+impl CloudLayer {
+    /// A volumetric layer of small heaps of clouds at mid altitude.
+    pub fn altocumulus() -> Self {
+        Self {
+            mode: CloudLayerMode::Volumetric,
+            bottom_height: 4000.0,
+            top_height: 4600.0,
+            coverage: 0.4,
+            density: 0.02,
+            base_scale: 3.0,
+            detail_scale: 42.0,
+            cloud_type: 1.0 / 3.0,
+            wind_velocity: Vec3::new(-3.0, 0.0, 4.0),
+            use_weather_map: false,
+        }
+    }
+
+    /// A flat layer of wispy streaks high up in the sky.
+    pub fn cirrus() -> Self {
+        Self {
+            mode: CloudLayerMode::Flat,
+            bottom_height: 8000.0,
+            top_height: 8300.0,
+            coverage: 0.35,
+            density: 0.01,
+            base_scale: 0.5,
+            detail_scale: 4.0,
+            cloud_type: 0.0,
+            wind_velocity: Vec3::new(-12.0, 0.0, 6.0),
+            use_weather_map: false,
+        }
+    }
+
+    /// A flat, thin veil which covers most of the sky high up.
+    pub fn cirrostratus() -> Self {
+        Self {
+            coverage: 0.8,
+            density: 0.002,
+            base_scale: 0.2,
+            ..Self::cirrus()
+        }
+    }
+}
+
+/// Layers of clouds in addition to the main layer of
+/// [`CloudsConfig`](crate::config::CloudsConfig), such as high cirrus above low cumulus.
+///
+/// Each layer has its own heights, coverage, noise scale, wind and density. The layers shouldn't
+/// overlap each other or the main layer. At most [`MAX_CLOUD_LAYERS`] layers are rendered, any
+/// further layers are ignored. The clouds of a layer only shadow themselves, but all layers cast
+/// shadows onto the [`CloudsShadowMap`](crate::shadows::CloudsShadowMap).
+///
+/// The resource gets added automatically by `CloudsPlugin` without any layers.
+///
+/// # Example
+///
+/// ```rust ignore
+/// App::new()
+///     .add_plugins((DefaultPlugins, CloudsPlugin))
+///     .insert_resource(CloudsLayers {
+///         layers: vec![CloudLayer::altocumulus(), CloudLayer::cirrus()],
+///     })
+///     .run();
+/// ```
+#[derive(Resource, Clone, Debug, Default, PartialEq, ExtractResource)]
+pub struct CloudsLayers {
+    /// The additional layers.
+    pub layers: Vec<CloudLayer>,
+}
@@ -1,18 +1,34 @@
 #![doc = include_str!("../README.md")]
 
+mod atmosphere;
 mod compute;
 /// Controls the compute shader which renders the volumetric clouds.
 pub mod config;
+/// A cubemap of the sky and the clouds for skyboxes and environment lighting.
+pub mod environment_map;
 /// A utility plugin to control the camera using keyboard and mouse.
 #[cfg(feature = "fly_camera")]
 pub mod fly_camera;
 mod images;
+/// Additional layers of clouds above or below the main layer.
+pub mod layers;
+/// The sun of the clouds, which can follow a directional light.
+pub mod light;
+/// The noise textures which shape the clouds.
+pub mod noise;
+/// Vertical density profiles of the types of clouds.
+pub mod profiles;
 mod render;
-mod skybox;
+/// Shadows which the clouds cast onto the scene.
+pub mod shadows;
+/// A day/night cycle which moves the sun of the clouds.
+pub mod time_of_day;
 #[cfg(feature = "debug")]
 mod ui;
 mod uniforms;
-use bevy::prelude::*;
+/// A weather map which controls where clouds form.
+pub mod weather;
+use bevy::{camera::CameraUpdateSystems, prelude::*};
 
 #[cfg(feature = "debug")]
 use self::ui::ui_system;
@@ -21,74 +37,126 @@ use bevy_egui::EguiPrimaryContextPass;
 
 use crate::{
     compute::CameraMatrices,
-    config::CloudsConfig,
-    images::build_images,
-    render::{CloudsMaterial, CloudsShaderPlugin},
-    skybox::{SkyboxMaterials, init_skybox_mesh, setup_daylight, update_skybox_transform},
-    uniforms::CloudsImage,
+    config::{CloudsConfig, VolumetricClouds},
+    images::{CloudsTextureFormats, build_render_images},
+    layers::CloudsLayers,
+    light::update_sun_from_light,
+    noise::{CloudsNoise, ExportCloudsNoise, export_noise, update_noise_images},
+    profiles::{CloudsProfiles, update_profiles_image},
+    render::{CloudsRenderPlugin, CloudsShaderPlugin},
+    time_of_day::{Sunrise, Sunset, TimeOfDay, update_time_of_day},
+    uniforms::CloudsRenderImages,
 };
 
-use self::compute::CloudsComputePlugin;
+use self::{
+    atmosphere::CloudsAtmospherePlugin, compute::CloudsComputePlugin,
+    environment_map::CloudsEnvironmentMapPlugin, shadows::CloudsShadowsPlugin,
+    weather::CloudsWeatherPlugin,
+};
 
 /// A plugin for rendering clouds.
 ///
-/// The configuration of the clouds can be changed using the [`CloudsConfig`] resource.
+/// The clouds are rendered for every camera with the [`VolumetricClouds`] component. Their
+/// configuration can be changed using the [`CloudsConfig`] resource.
 pub struct CloudsPlugin;
 
 impl Plugin for CloudsPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(CloudsConfig::default())
-            .add_plugins((CloudsComputePlugin, CloudsShaderPlugin))
-            .add_systems(Startup, (clouds_setup, setup_daylight))
+            .init_resource::<CloudsProfiles>()
+            .init_resource::<CloudsLayers>()
+            .init_resource::<CloudsNoise>()
+            .add_plugins((
+                CloudsComputePlugin,
+                CloudsRenderPlugin,
+                CloudsShaderPlugin,
+                CloudsEnvironmentMapPlugin,
+                CloudsAtmospherePlugin,
+                CloudsShadowsPlugin,
+                CloudsWeatherPlugin,
+            ))
+            .add_message::<Sunrise>()
+            .add_message::<Sunset>()
+            .add_message::<ExportCloudsNoise>()
+            .add_systems(
+                PostUpdate,
+                // The projection and viewport size of cameras, including cameras that render to an
+                // image, are computed in `CameraUpdateSystems`.
+                (update_camera_matrices, update_render_images)
+                    .after(TransformSystems::Propagate)
+                    .after(CameraUpdateSystems),
+            )
             .add_systems(
                 PostUpdate,
-                (update_skybox_transform, update_camera_matrices)
-                    .after(TransformSystems::Propagate),
+                (
+                    update_time_of_day
+                        .run_if(resource_exists::<TimeOfDay>)
+                        .before(TransformSystems::Propagate),
+                    update_sun_from_light.after(TransformSystems::Propagate),
+                    update_profiles_image,
+                    (update_noise_images, export_noise).chain(),
+                ),
             );
         #[cfg(feature = "debug")]
         app.add_systems(EguiPrimaryContextPass, ui_system);
     }
 }
 
-fn clouds_setup(
-    mut commands: Commands,
-    images: ResMut<Assets<Image>>,
-    meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<CloudsMaterial>>,
-) {
-    let (cloud_render_image, cloud_atlas_image, cloud_worley_image, sky_image) =
-        build_images(images);
-
-    let material = materials.add(CloudsMaterial {
-        cloud_render_image: cloud_render_image.clone(),
-        cloud_atlas_image: cloud_atlas_image.clone(),
-        cloud_worley_image: cloud_worley_image.clone(),
-        sky_image: sky_image.clone(),
-    });
-    init_skybox_mesh(
-        &mut commands,
-        meshes,
-        SkyboxMaterials::from_one_material(MeshMaterial3d(material.clone())),
-    );
-    commands.insert_resource(CloudsImage {
-        cloud_render_image,
-        cloud_atlas_image,
-        cloud_worley_image,
-        sky_image,
-    });
-    commands.insert_resource(CameraMatrices {
-        translation: Vec3::ZERO,
-        inverse_camera_projection: Mat4::IDENTITY,
-        inverse_camera_view: Mat4::IDENTITY,
-    });
+fn update_camera_matrices(mut cameras: Query<(&GlobalTransform, &Camera, &mut CameraMatrices)>) {
+    for (camera_transform, camera, mut matrices) in &mut cameras {
+        matrices.previous_translation = matrices.translation;
+        matrices.previous_clip_from_world = matrices.clip_from_world;
+        matrices.translation = camera_transform.translation();
+        matrices.inverse_camera_view = camera_transform.to_matrix();
+        matrices.inverse_camera_projection = camera.computed.clip_from_view.inverse();
+        matrices.clip_from_world =
+            camera.computed.clip_from_view * matrices.inverse_camera_view.inverse();
+    }
 }
 
-fn update_camera_matrices(
-    cam_query: Single<(&GlobalTransform, &Camera)>,
-    mut config: ResMut<CameraMatrices>,
+/// Allocate the render targets of the clouds of each camera with [`VolumetricClouds`], and
+/// reallocate them when the size of the camera's viewport or the [`RenderScale`] changed.
+///
+/// The viewport is sized after the camera's render target, so this also covers cameras which
+/// render to an [`Image`] whose size is changed.
+///
+/// The images are rendered on the GPU only, so they can't be resized in place. Instead, new images
+/// are created and the [`CloudsRenderImages`] of the camera are swapped out.
+///
+/// [`RenderScale`]: config::RenderScale
+fn update_render_images(
+    mut commands: Commands,
+    config: Res<CloudsConfig>,
+    cameras: Query<(
+        Entity,
+        &Camera,
+        &VolumetricClouds,
+        Option<&CloudsRenderImages>,
+    )>,
+    removed: Query<Entity, (With<CloudsRenderImages>, Without<VolumetricClouds>)>,
+    mut images: ResMut<Assets<Image>>,
+    formats: Res<CloudsTextureFormats>,
 ) {
-    let (camera_transform, camera) = *cam_query;
-    config.translation = camera_transform.translation();
-    config.inverse_camera_view = camera_transform.to_matrix();
-    config.inverse_camera_projection = camera.computed.clip_from_view.inverse();
+    for (entity, camera, volumetric_clouds, render_images) in &cameras {
+        let Some(viewport_size) = camera.physical_viewport_size() else {
+            continue;
+        };
+        let size = volumetric_clouds
+            .apply_to(*config)
+            .render_scale
+            .image_size(viewport_size);
+        if render_images
+            .and_then(|render_images| images.get(&render_images.cloud_render_image))
+            .is_some_and(|image| image.size() == size)
+        {
+            continue;
+        }
+        commands
+            .entity(entity)
+            .insert(build_render_images(&mut images, size, &formats));
+    }
+
+    for entity in &removed {
+        commands.entity(entity).remove::<CloudsRenderImages>();
+    }
 }
@@ -0,0 +1,84 @@
+use bevy::{
+    light::light_consts::lux::FULL_DAYLIGHT, prelude::*,
+    render::extract_component::ExtractComponent,
+};
+
+use crate::config::{CloudsConfig, DEFAULT_SUN_COLOR};
+
+/// Marks the [`DirectionalLight`] that acts as the sun of the clouds.
+///
+/// Add this to your own light to let the clouds follow it. Whenever the light moves or changes, its
+/// direction and its color and illuminance are copied to [`CloudsConfig::sun_dir`] and
+/// [`CloudsConfig::sun_color`]. A white light of [`FULL_DAYLIGHT`] gives the default `sun_color`.
+/// Cameras with Bevy's [`Atmosphere`](bevy::pbr::Atmosphere) are lit by this light as well.
+///
+/// Without a light with this component, the sun of the clouds is set through [`CloudsConfig`].
+/// Only a single light should have it.
+///
+/// # Example
+///
+/// ```rust ignore
+/// commands.spawn((
+///     DirectionalLight {
+///         illuminance: light_consts::lux::FULL_DAYLIGHT,
+///         ..default()
+///     },
+///     Transform::from_xyz(1.0, 0.2, 0.0).looking_at(Vec3::ZERO, Vec3::Y),
+///     CloudsSun,
+/// ));
+/// ```
+#[derive(Component, Clone, Copy, Debug, Default, ExtractComponent)]
+#[require(DirectionalLight)]
+pub struct CloudsSun;
+
+/// Copy the direction and color of the light with [`CloudsSun`] to the [`CloudsConfig`] whenever
+/// the light changes.
+#[expect(clippy::type_complexity)]
+pub(crate) fn update_sun_from_light(
+    mut config: ResMut<CloudsConfig>,
+    suns: Query<
+        (&DirectionalLight, &GlobalTransform),
+        (
+            With<CloudsSun>,
+            Or<(Changed<DirectionalLight>, Changed<GlobalTransform>)>,
+        ),
+    >,
+) {
+    let Ok((light, transform)) = suns.single() else {
+        return;
+    };
+
+    // The light shines along its forward direction, `sun_dir` points towards the sun.
+    config.sun_dir = transform.back().extend(0.0);
+    config.sun_color = sun_color(light.color, light.illuminance);
+}
+
+/// The [`CloudsConfig::sun_color`] of a directional light.
+///
+/// A white light of [`FULL_DAYLIGHT`] gives the default `sun_color`, the color of other lights
+/// tints it and their illuminance scales it.
+pub(crate) fn sun_color(color: Color, illuminance: f32) -> Vec4 {
+    DEFAULT_SUN_COLOR * color.to_linear().to_vec4() * (illuminance / FULL_DAYLIGHT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_daylight_gives_the_default_sun_color() {
+        assert_eq!(
+            sun_color(Color::WHITE, FULL_DAYLIGHT),
+            CloudsConfig::default().sun_color
+        );
+    }
+
+    #[test]
+    fn sun_color_scales_with_the_light() {
+        let half = sun_color(Color::WHITE, FULL_DAYLIGHT / 2.0);
+        assert!(half.abs_diff_eq(DEFAULT_SUN_COLOR / 2.0, 1.0e-6));
+
+        let red = sun_color(Color::linear_rgb(1.0, 0.0, 0.0), FULL_DAYLIGHT);
+        assert_eq!(red.truncate(), Vec3::new(DEFAULT_SUN_COLOR.x, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,455 @@
+use core::sync::atomic::Ordering;
+use std::path::PathBuf;
+
+use bevy::{
+    asset::AssetPath,
+    image::{ImageLoaderSettings, TextureFormatPixelInfo},
+    prelude::*,
+    render::{
+        gpu_readback::{Readback, ReadbackComplete},
+        render_resource::{Extent3d, TextureFormat},
+        renderer::RenderDevice,
+    },
+};
+
+use crate::{
+    images::{CloudsTextureFormats, build_noise_images, noise_sampler},
+    uniforms::CloudsNoiseImages,
+};
+
+/// The noise textures which shape the clouds.
+///
+/// By default, the noise is generated on the GPU when the app starts, which causes a hitch. The
+/// generated noise can be saved with [`ExportCloudsNoise`] and loaded back with
+/// [`CloudsNoise::load`] to skip generating it, or replaced by your own textures:
+/// - `atlas`: a tiling 2D texture of the shape of the clouds. The red channel holds the base
+///   shape, the green and blue channels remap and erode it towards the top of the clouds. The
+///   green channel is offset by 1 such that it fits a normalized format.
+/// - `worley`: a tiling 3D texture whose red channel erodes the details of the clouds. It gets
+///   filtered trilinearly, so it needs a filterable format and should have mip levels.
+/// - `curl`: a tiling 2D texture of horizontal directions in the red and green channels, mapped
+///   from -1..1 to 0..1, which offset the detail noise. It needs a filterable format too.
+///
+/// The textures need a float or normalized integer format with linear color data.
+///
+/// # Example
+///
+/// ```rust ignore
+/// fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+///     commands.insert_resource(CloudsNoise::load(
+///         &asset_server,
+///         "clouds_atlas.ktx2",
+///         "clouds_worley.ktx2",
+///         "clouds_curl.ktx2",
+///     ));
+/// }
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub enum CloudsNoise {
+    /// Generate the noise on the GPU.
+    Generated {
+        /// Width, height and depth of the 3D Worley noise, a power of two such as 64 or 128.
+        /// Larger sizes give finer details close up, at the cost of memory and a longer hitch.
+        worley_size: u32,
+    },
+    /// Use the given textures.
+    Images {
+        /// The tiling 2D texture of the shape of the clouds.
+        atlas: Handle<Image>,
+        /// The tiling 3D texture of the details of the clouds.
+        worley: Handle<Image>,
+        /// The tiling 2D texture of the directions which distort the details of the clouds.
+        curl: Handle<Image>,
+    },
+}
+
+impl Default for CloudsNoise {
+    fn default() -> Self {
+        Self::Generated { worley_size: 64 }
+    }
+}
+
+impl CloudsNoise {
+    /// Loads the textures from files, such as the KTX2 files written by [`ExportCloudsNoise`].
+    pub fn load<'a>(
+        asset_server: &AssetServer,
+        atlas: impl Into<AssetPath<'a>>,
+        worley: impl Into<AssetPath<'a>>,
+        curl: impl Into<AssetPath<'a>>,
+    ) -> Self {
+        let load = |path: AssetPath<'a>| {
+            asset_server.load_with_settings(path, |settings: &mut ImageLoaderSettings| {
+                settings.is_srgb = false;
+                settings.sampler = noise_sampler();
+            })
+        };
+        Self::Images {
+            atlas: load(atlas.into()),
+            worley: load(worley.into()),
+            curl: load(curl.into()),
+        }
+    }
+}
+
+/// Write this message to save the generated noise textures of the clouds to KTX2 files, which can
+/// be loaded with [`CloudsNoise::load`].
+///
+/// The files are written as soon as the noise has been generated. Only [`CloudsNoise::Generated`]
+/// noise can be exported. Only the first mip level of the Worley noise is written, so the details
+/// of clouds far away may flicker a bit more when the files are loaded.
+///
+/// # Example
+///
+/// ```rust ignore
+/// fn export(mut exports: MessageWriter<ExportCloudsNoise>) {
+///     exports.write(ExportCloudsNoise {
+///         atlas_path: "assets/clouds_atlas.ktx2".into(),
+///         worley_path: "assets/clouds_worley.ktx2".into(),
+///         curl_path: "assets/clouds_curl.ktx2".into(),
+///     });
+/// }
+/// ```
+#[derive(Message, Clone, Debug)]
+pub struct ExportCloudsNoise {
+    /// Path of the file of the 2D atlas.
+    pub atlas_path: PathBuf,
+    /// Path of the file of the 3D Worley noise.
+    pub worley_path: PathBuf,
+    /// Path of the file of the 2D curl noise.
+    pub curl_path: PathBuf,
+}
+
+/// Build or swap out the [`CloudsNoiseImages`] whenever the [`CloudsNoise`] changes.
+pub(crate) fn update_noise_images(
+    mut commands: Commands,
+    noise: Res<CloudsNoise>,
+    mut images: ResMut<Assets<Image>>,
+    formats: Res<CloudsTextureFormats>,
+) {
+    if !noise.is_changed() {
+        return;
+    }
+    let noise_images = match noise.as_ref() {
+        CloudsNoise::Generated { worley_size } => {
+            build_noise_images(&mut images, *worley_size, &formats)
+        }
+        CloudsNoise::Images {
+            atlas,
+            worley,
+            curl,
+        } => CloudsNoiseImages {
+            cloud_atlas_image: atlas.clone(),
+            cloud_worley_image: worley.clone(),
+            cloud_curl_image: curl.clone(),
+            generated: None,
+        },
+    };
+    commands.insert_resource(noise_images);
+}
+
+/// Read back the noise textures for each [`ExportCloudsNoise`] once they have been generated, and
+/// write them to their files.
+pub(crate) fn export_noise(
+    mut commands: Commands,
+    mut exports: MessageReader<ExportCloudsNoise>,
+    mut pending: Local<Vec<ExportCloudsNoise>>,
+    noise_images: Option<Res<CloudsNoiseImages>>,
+    images: Res<Assets<Image>>,
+) {
+    pending.extend(exports.read().cloned());
+    if pending.is_empty() {
+        return;
+    }
+    let Some(noise_images) = noise_images else {
+        return;
+    };
+    let Some(generated) = &noise_images.generated else {
+        for export in pending.drain(..) {
+            warn!(
+                "Only generated noise of the clouds can be exported, not writing {}",
+                export.atlas_path.display()
+            );
+        }
+        return;
+    };
+    if !generated.load(Ordering::Acquire) {
+        return;
+    }
+
+    for export in pending.drain(..) {
+        for (handle, path) in [
+            (&noise_images.cloud_atlas_image, export.atlas_path),
+            (&noise_images.cloud_worley_image, export.worley_path),
+            (&noise_images.cloud_curl_image, export.curl_path),
+        ] {
+            let Some(image) = images.get(handle) else {
+                continue;
+            };
+            let descriptor = &image.texture_descriptor;
+            let (size, format) = (descriptor.size, descriptor.format);
+            spawn_export(&mut commands, handle.clone(), size, format, path);
+        }
+    }
+}
+
+/// Read back the first mip level of an image of `size` and `format` and write it to a KTX2 file.
+fn spawn_export(
+    commands: &mut Commands,
+    image: Handle<Image>,
+    size: Extent3d,
+    format: TextureFormat,
+    path: PathBuf,
+) {
+    commands.spawn(Readback::texture(image)).observe(
+        move |readback: On<ReadbackComplete>, mut commands: Commands| {
+            // The image is read back every frame until the entity is despawned.
+            commands.entity(readback.entity).despawn();
+
+            let data = remove_row_padding(&readback.data, size, format);
+            let Some(file) = encode_ktx2(size, format, &data) else {
+                error!("The format {format:?} can't be exported to KTX2");
+                return;
+            };
+            match std::fs::write(&path, file) {
+                Ok(()) => info!("Exported the noise of the clouds to {}", path.display()),
+                Err(error) => error!(
+                    "Failed to export the noise of the clouds to {}: {error}",
+                    path.display()
+                ),
+            }
+        },
+    );
+}
+
+/// Remove the padding of the rows of a texture that was copied to a buffer, which aligns each row
+/// to `COPY_BYTES_PER_ROW_ALIGNMENT`.
+fn remove_row_padding(data: &[u8], size: Extent3d, format: TextureFormat) -> Vec<u8> {
+    let row_bytes = size.width as usize * format.pixel_size().unwrap_or_default();
+    let padded_row_bytes = RenderDevice::align_copy_bytes_per_row(row_bytes);
+    let rows = (size.height * size.depth_or_array_layers) as usize;
+    data.chunks(padded_row_bytes)
+        .take(rows)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect()
+}
+
+/// The file identifier at the start of every KTX2 file.
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+
+/// The Vulkan format, the size of a component in bytes, the number of components and whether the
+/// components are floats, of the formats that can be written to KTX2 files.
+fn ktx2_format(format: TextureFormat) -> Option<(u32, u32, u32, bool)> {
+    match format {
+        TextureFormat::R8Unorm => Some((9, 1, 1, false)),
+        TextureFormat::Rg8Unorm => Some((16, 1, 2, false)),
+        TextureFormat::Rgba8Unorm => Some((37, 1, 4, false)),
+        TextureFormat::Rgba16Float => Some((97, 2, 4, true)),
+        TextureFormat::Rgba32Float => Some((109, 4, 4, true)),
+        _ => None,
+    }
+}
+
+/// Encode the single mip level of an uncompressed 2D or 3D texture as a KTX2 file.
+fn encode_ktx2(size: Extent3d, format: TextureFormat, data: &[u8]) -> Option<Vec<u8>> {
+    const HEADER_LENGTH: usize = 80;
+    const LEVEL_INDEX_LENGTH: usize = 24;
+
+    let (vk_format, type_size, components, is_float) = ktx2_format(format)?;
+    let bits = type_size * 8;
+
+    // The data format descriptor, with a basic block that has a sample per component.
+    let block_size = 24 + 16 * components;
+    let mut dfd = Vec::new();
+    dfd.extend((4 + block_size).to_le_bytes());
+    // Khronos vendor and basic descriptor type, version 2.
+    dfd.extend(0u32.to_le_bytes());
+    dfd.extend((2 | (block_size << 16)).to_le_bytes());
+    // RGBSDA color model, BT.709 primaries, linear transfer function and straight alpha.
+    dfd.extend([1, 1, 1, 0]);
+    // Texel blocks of 1x1x1x1 texels in a single plane.
+    dfd.extend([0; 4]);
+    dfd.extend([(type_size * components) as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for component in 0..components {
+        // Red, green, blue and alpha, floats are signed as well.
+        let channel = [0, 1, 2, 15][component as usize] | if is_float { 0xc0 } else { 0 };
+        let (lower, upper) = if is_float {
+            ((-1.0f32).to_bits(), 1.0f32.to_bits())
+        } else {
+            (0, (1 << bits) - 1)
+        };
+        dfd.extend(((component * bits) | ((bits - 1) << 16) | (channel << 24)).to_le_bytes());
+        dfd.extend(0u32.to_le_bytes());
+        dfd.extend(lower.to_le_bytes());
+        dfd.extend(upper.to_le_bytes());
+    }
+
+    let dfd_offset = HEADER_LENGTH + LEVEL_INDEX_LENGTH;
+    // The level data is aligned to the size of a texel, which divides 16.
+    let data_offset = (dfd_offset + dfd.len()).next_multiple_of(16);
+    let depth = if size.depth_or_array_layers > 1 {
+        size.depth_or_array_layers
+    } else {
+        0
+    };
+
+    let mut file = Vec::with_capacity(data_offset + data.len());
+    file.extend(KTX2_IDENTIFIER);
+    // Format, type size, width, height, depth, layers, faces, levels and no supercompression.
+    for value in [
+        vk_format,
+        type_size,
+        size.width,
+        size.height,
+        depth,
+        0,
+        1,
+        1,
+        0,
+    ] {
+        file.extend(value.to_le_bytes());
+    }
+    // The data format descriptor, no key/value data and no supercompression global data.
+    file.extend((dfd_offset as u32).to_le_bytes());
+    file.extend((dfd.len() as u32).to_le_bytes());
+    file.extend([0; 8 + 16]);
+    // The level index with the offset, the length and the uncompressed length of the level.
+    for value in [data_offset, data.len(), data.len()] {
+        file.extend((value as u64).to_le_bytes());
+    }
+    file.extend(dfd);
+    file.resize(data_offset, 0);
+    file.extend(data);
+    Some(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        asset::RenderAssetUsages,
+        image::{CompressedImageFormats, ImageSampler, ImageType},
+        render::render_resource::TextureDimension,
+    };
+
+    use super::*;
+
+    fn read_u32(file: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(file: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(file[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Encode `data` and load it back with Bevy's KTX2 loader.
+    fn encode_and_load(size: Extent3d, format: TextureFormat, data: &[u8]) -> (Vec<u8>, Image) {
+        let file = encode_ktx2(size, format, data).unwrap();
+        let image = Image::from_buffer(
+            &file,
+            ImageType::Extension("ktx2"),
+            CompressedImageFormats::NONE,
+            false,
+            ImageSampler::Default,
+            RenderAssetUsages::default(),
+        )
+        .unwrap();
+        (file, image)
+    }
+
+    #[test]
+    fn encodes_the_header_and_the_level_index() {
+        let size = Extent3d {
+            width: 4,
+            height: 2,
+            depth_or_array_layers: 1,
+        };
+        let data: Vec<u8> = (0..32).collect();
+        let file = encode_ktx2(size, TextureFormat::Rgba8Unorm, &data).unwrap();
+
+        assert_eq!(file[..12], KTX2_IDENTIFIER);
+        // Format, type size, width, height, depth, layers, faces and levels.
+        let header: Vec<u32> = (0..8).map(|i| read_u32(&file, 12 + 4 * i)).collect();
+        assert_eq!(header, [37, 1, 4, 2, 0, 0, 1, 1]);
+
+        // The data format descriptor follows the level index, with a sample per component.
+        let (dfd_offset, dfd_length) = (read_u32(&file, 48), read_u32(&file, 52));
+        assert_eq!(dfd_offset, 80 + 24);
+        assert_eq!(dfd_length, 4 + 24 + 16 * 4);
+        assert_eq!(read_u32(&file, dfd_offset as usize), dfd_length);
+
+        let (level_offset, level_length) = (read_u64(&file, 80), read_u64(&file, 88));
+        assert_eq!(level_offset % 16, 0);
+        assert!(level_offset >= u64::from(dfd_offset + dfd_length));
+        assert_eq!(level_length, data.len() as u64);
+        assert_eq!(read_u64(&file, 96), level_length);
+        assert_eq!(file.len() as u64, level_offset + level_length);
+        assert_eq!(file[level_offset as usize..], data);
+    }
+
+    #[test]
+    fn loads_encoded_2d_images() {
+        let size = Extent3d {
+            width: 4,
+            height: 2,
+            depth_or_array_layers: 1,
+        };
+        for format in [TextureFormat::R8Unorm, TextureFormat::Rgba8Unorm] {
+            let data: Vec<u8> = (0..8 * format.pixel_size().unwrap() as u8).collect();
+            let (_, image) = encode_and_load(size, format, &data);
+
+            assert_eq!(image.texture_descriptor.format, format);
+            assert_eq!(image.texture_descriptor.dimension, TextureDimension::D2);
+            assert_eq!(image.texture_descriptor.size, size);
+            assert_eq!(image.data.as_deref(), Some(data.as_slice()));
+        }
+    }
+
+    #[test]
+    fn loads_encoded_3d_images() {
+        let size = Extent3d {
+            width: 2,
+            height: 2,
+            depth_or_array_layers: 2,
+        };
+        let data: Vec<u8> = (0..8 * 8).collect();
+        let (file, image) = encode_and_load(size, TextureFormat::Rgba16Float, &data);
+
+        assert_eq!(read_u32(&file, 28), 2);
+        assert_eq!(image.texture_descriptor.format, TextureFormat::Rgba16Float);
+        assert_eq!(image.texture_descriptor.dimension, TextureDimension::D3);
+        assert_eq!(image.texture_descriptor.size, size);
+        assert_eq!(image.data.as_deref(), Some(data.as_slice()));
+    }
+
+    #[test]
+    fn unsupported_formats_are_not_encoded() {
+        let size = Extent3d::default();
+        assert!(encode_ktx2(size, TextureFormat::Depth32Float, &[]).is_none());
+    }
+
+    #[test]
+    fn removes_the_padding_of_rows() {
+        // Rows of 3 Rgba8 pixels are padded from 12 to 256 bytes.
+        let size = Extent3d {
+            width: 3,
+            height: 2,
+            depth_or_array_layers: 2,
+        };
+        let padded_row_bytes = RenderDevice::align_copy_bytes_per_row(12);
+        assert_eq!(padded_row_bytes, 256);
+
+        let mut data = vec![0xff; padded_row_bytes * 4];
+        for row in 0..4 {
+            for byte in 0..12 {
+                data[row * padded_row_bytes + byte] = (row * 12 + byte) as u8;
+            }
+        }
+        let expected: Vec<u8> = (0..48).collect();
+        assert_eq!(
+            remove_row_padding(&data, size, TextureFormat::Rgba8Unorm),
+            expected
+        );
+    }
+}
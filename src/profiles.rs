@@ -0,0 +1,151 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+use crate::images::build_profiles_image;
+
+/// The density of a type of cloud at each height of the cloud layer.
+///
+/// The profile is given by points of the normalized height in the cloud layer, from 0 at
+/// `clouds_bottom_height` to 1 at `clouds_top_height`, and the density at that height. The density
+/// is interpolated linearly between the points. Densities above 1 let clouds grow wider at that
+/// height, like the anvil of a cumulonimbus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloudProfile {
+    /// Pairs of a normalized height and a density, sorted by height.
+    pub points: Vec<Vec2>,
+}
+
+impl CloudProfile {
+    /// Creates a profile from pairs of a normalized height and a density, sorted by height.
+    pub fn new(points: impl Into<Vec<Vec2>>) -> Self {
+        Self {
+            points: points.into(),
+        }
+    }
+
+    /// A flat layer of clouds at the bottom of the cloud layer.
+    pub fn stratus() -> Self {
+        Self::new([
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.1, 1.0),
+            Vec2::new(0.2, 1.0),
+            Vec2::new(0.4, 0.0),
+        ])
+    }
+
+    /// A layer of lumpy clouds in the lower half of the cloud layer.
+    pub fn stratocumulus() -> Self {
+        Self::new([
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.1, 1.0),
+            Vec2::new(0.4, 1.0),
+            Vec2::new(0.6, 0.0),
+        ])
+    }
+
+    /// Heaps of clouds which fill most of the cloud layer.
+    pub fn cumulus() -> Self {
+        Self::new([
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.1, 1.0),
+            Vec2::new(0.8, 1.0),
+            Vec2::new(1.0, 0.5),
+        ])
+    }
+
+    /// Towering storm clouds which fill the cloud layer and spread out into an anvil at the top.
+    pub fn cumulonimbus() -> Self {
+        Self::new([
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.05, 1.0),
+            Vec2::new(0.7, 1.0),
+            Vec2::new(0.85, 1.4),
+            Vec2::new(0.95, 1.4),
+            Vec2::new(1.0, 0.0),
+        ])
+    }
+
+    /// The density at a normalized height in the cloud layer.
+    pub fn density(&self, height: f32) -> f32 {
+        let Some(first) = self.points.first() else {
+            return 1.0;
+        };
+        if height <= first.x {
+            return first.y;
+        }
+        for pair in self.points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            if height <= b.x {
+                let t = (height - a.x) / (b.x - a.x).max(f32::EPSILON);
+                return a.y + (b.y - a.y) * t;
+            }
+        }
+        self.points[self.points.len() - 1].y
+    }
+}
+
+/// The vertical density profiles of the types of clouds.
+///
+/// The type of the clouds is a value between 0 and 1, given by [`CloudsConfig::cloud_type`] or
+/// per point by the [`CloudsWeatherMap`]. A type of 0 selects the first profile and 1 the last,
+/// types in between blend the neighbouring profiles. The default profiles are stratus,
+/// stratocumulus, cumulus and cumulonimbus, at types 0, 1/3, 2/3 and 1.
+///
+/// The resource gets added automatically by `CloudsPlugin`, change it to use other profiles.
+///
+/// # Example
+///
+/// ```rust ignore
+/// App::new()
+///     .add_plugins((DefaultPlugins, CloudsPlugin))
+///     .insert_resource(CloudsProfiles {
+///         profiles: vec![CloudProfile::stratus(), CloudProfile::cumulonimbus()],
+///     })
+///     .run();
+/// ```
+///
+/// [`CloudsConfig::cloud_type`]: crate::config::CloudsConfig::cloud_type
+/// [`CloudsWeatherMap`]: crate::weather::CloudsWeatherMap
+#[derive(Resource, Clone, Debug, PartialEq)]
+pub struct CloudsProfiles {
+    /// The profiles in the order of the type of the clouds.
+    pub profiles: Vec<CloudProfile>,
+}
+
+impl Default for CloudsProfiles {
+    fn default() -> Self {
+        Self {
+            profiles: vec![
+                CloudProfile::stratus(),
+                CloudProfile::stratocumulus(),
+                CloudProfile::cumulus(),
+                CloudProfile::cumulonimbus(),
+            ],
+        }
+    }
+}
+
+/// The [`CloudsProfiles`] sampled into a lookup table, with a row per profile.
+#[derive(Resource, Clone, ExtractResource)]
+pub(crate) struct CloudsProfilesImage(pub Handle<Image>);
+
+/// Sample the [`CloudsProfiles`] into [`CloudsProfilesImage`] whenever they change.
+pub(crate) fn update_profiles_image(
+    mut commands: Commands,
+    profiles: Res<CloudsProfiles>,
+    profiles_image: Option<Res<CloudsProfilesImage>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    match profiles_image {
+        Some(profiles_image) if profiles.is_changed() => {
+            // The number of profiles might have changed, so the image is replaced.
+            images
+                .insert(&profiles_image.0, build_profiles_image(&profiles.profiles))
+                .expect("the profiles image is never removed");
+        }
+        Some(_) => {}
+        None => {
+            let image = images.add(build_profiles_image(&profiles.profiles));
+            commands.insert_resource(CloudsProfilesImage(image));
+        }
+    }
+}
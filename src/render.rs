@@ -1,47 +1,434 @@
 use bevy::{
-    asset::{AssetPath, embedded_asset, embedded_path},
+    asset::{embedded_asset, load_embedded_asset},
+    core_pipeline::{
+        FullscreenShader,
+        core_3d::graph::{Core3d, Node3d},
+        prepass::ViewPrepassTextures,
+    },
+    ecs::query::QueryItem,
+    pbr::ExtractedAtmosphere,
     prelude::*,
-    reflect::TypePath,
-    render::render_resource::AsBindGroup,
-    shader::{ShaderRef, load_shader_library},
+    render::{
+        Render, RenderApp, RenderSystems,
+        camera::ExtractedCamera,
+        render_asset::RenderAssets,
+        render_graph::{
+            NodeRunError, RenderGraphContext, RenderGraphExt, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            BindGroup, BindGroupEntries, BindGroupLayoutDescriptor, BindGroupLayoutEntries,
+            BlendComponent, BlendFactor, BlendOperation, BlendState, CachedComputePipelineId,
+            CachedRenderPipelineId, ColorTargetState, ColorWrites, ComputePassDescriptor,
+            FragmentState, MultisampleState, PipelineCache, RenderPassDescriptor,
+            RenderPipelineDescriptor, ShaderStages, SpecializedComputePipelines,
+            SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
+            TextureSampleType,
+            binding_types::{
+                texture_2d, texture_depth_2d, texture_depth_2d_multisampled, uniform_buffer,
+            },
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::GpuImage,
+        view::{Msaa, ViewTarget},
+    },
+    shader::load_shader_library,
 };
 
-#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
-pub(crate) struct CloudsMaterial {
-    #[texture(100, visibility(vertex, fragment))]
-    #[sampler(101, visibility(vertex, fragment))]
-    pub cloud_render_image: Handle<Image>,
+use crate::{
+    atmosphere::CloudsAtmosphereBindGroup,
+    compute::{
+        CloudsLabel, CloudsNoiseBindGroup, CloudsNoiseReady, CloudsPipeline, CloudsPipelineKey,
+        CloudsUniformBindGroup, CloudsUniformOffset, CloudsViewImages, WORKGROUP_SIZE,
+    },
+    config::{CloudsConfig, VolumetricClouds},
+    uniforms::{CloudsRenderImages, CloudsUniform, CloudsUniformBuffer},
+};
+
+pub(crate) struct CloudsShaderPlugin;
+
+impl Plugin for CloudsShaderPlugin {
+    fn build(&self, app: &mut App) {
+        load_shader_library!(app, "shaders/atmosphere.wgsl");
+        load_shader_library!(app, "shaders/common.wgsl");
+        load_shader_library!(app, "shaders/config.wgsl");
+        load_shader_library!(app, "shaders/shadows.wgsl");
+
+        embedded_asset!(app, "shaders/clouds.wgsl");
+        embedded_asset!(app, "shaders/clouds_compute.wgsl");
+        embedded_asset!(app, "shaders/clouds_noise.wgsl");
+        embedded_asset!(app, "shaders/clouds_shadows.wgsl");
+    }
+}
+
+/// The pipeline which composites the clouds and the sky over the opaque scene.
+#[derive(Resource)]
+struct CloudsCompositePipeline {
+    bind_group_layout: BindGroupLayoutDescriptor,
+    bind_group_layout_multisampled: BindGroupLayoutDescriptor,
+    fullscreen_shader: FullscreenShader,
+    shader: Handle<Shader>,
+}
+
+impl FromWorld for CloudsCompositePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let layout_entries = |depth_texture| {
+            BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    uniform_buffer::<CloudsUniform>(true),
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    depth_texture,
+                ),
+            )
+        };
+
+        Self {
+            bind_group_layout: BindGroupLayoutDescriptor::new(
+                "clouds_composite_bind_group_layout",
+                &layout_entries(texture_depth_2d()),
+            ),
+            bind_group_layout_multisampled: BindGroupLayoutDescriptor::new(
+                "clouds_composite_bind_group_layout_multisampled",
+                &layout_entries(texture_depth_2d_multisampled()),
+            ),
+            fullscreen_shader: world.resource::<FullscreenShader>().clone(),
+            shader: load_embedded_asset!(world, "shaders/clouds.wgsl"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct CloudsCompositePipelineKey {
+    texture_format: TextureFormat,
+    msaa_samples: u32,
+    /// Whether the view has Bevy's [`Atmosphere`](bevy::pbr::Atmosphere), which renders the sky.
+    atmosphere: bool,
+}
 
-    #[texture(102, visibility(vertex, fragment))]
-    #[sampler(103, visibility(vertex, fragment))]
-    pub cloud_atlas_image: Handle<Image>,
+impl SpecializedRenderPipeline for CloudsCompositePipeline {
+    type Key = CloudsCompositePipelineKey;
 
-    #[texture(104, visibility(vertex, fragment), dimension = "3d")]
-    #[sampler(105, visibility(vertex, fragment))]
-    pub cloud_worley_image: Handle<Image>,
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let mut shader_defs = Vec::new();
+        let bind_group_layout = if key.msaa_samples > 1 {
+            shader_defs.push("MULTISAMPLED".into());
+            self.bind_group_layout_multisampled.clone()
+        } else {
+            self.bind_group_layout.clone()
+        };
+        if key.atmosphere {
+            shader_defs.push("ATMOSPHERE".into());
+        }
 
-    #[texture(106, visibility(vertex, fragment))]
-    #[sampler(107, visibility(vertex, fragment))]
-    pub sky_image: Handle<Image>,
+        RenderPipelineDescriptor {
+            label: Some("clouds_composite_pipeline".into()),
+            layout: vec![bind_group_layout],
+            vertex: self.fullscreen_shader.to_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs,
+                targets: vec![Some(ColorTargetState {
+                    format: key.texture_format,
+                    // The scene behind the clouds is attenuated by their transmittance.
+                    blend: Some(BlendState {
+                        color: BlendComponent {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::SrcAlpha,
+                            operation: BlendOperation::Add,
+                        },
+                        alpha: BlendComponent {
+                            src_factor: BlendFactor::Zero,
+                            dst_factor: BlendFactor::One,
+                            operation: BlendOperation::Add,
+                        },
+                    }),
+                    write_mask: ColorWrites::ALL,
+                })],
+                ..default()
+            }),
+            multisample: MultisampleState {
+                count: key.msaa_samples,
+                ..default()
+            },
+            ..default()
+        }
+    }
+}
+
+/// The pipelines used to render the clouds of a view.
+#[derive(Component)]
+pub(crate) struct CloudsViewPipelines {
+    update: CachedComputePipelineId,
+    composite: CachedRenderPipelineId,
+    /// Whether the pipelines use the atmosphere of the view.
+    atmosphere: bool,
+}
+
+fn queue_clouds_pipelines(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    clouds_pipeline: Res<CloudsPipeline>,
+    composite_pipeline: Res<CloudsCompositePipeline>,
+    mut compute_pipelines: ResMut<SpecializedComputePipelines<CloudsPipeline>>,
+    mut render_pipelines: ResMut<SpecializedRenderPipelines<CloudsCompositePipeline>>,
+    views: Query<(Entity, &ViewTarget, &Msaa, Has<ExtractedAtmosphere>), With<VolumetricClouds>>,
+) {
+    for (entity, view_target, msaa, atmosphere) in &views {
+        let update = compute_pipelines.specialize(
+            &pipeline_cache,
+            &clouds_pipeline,
+            CloudsPipelineKey {
+                multisampled: msaa.samples() > 1,
+                atmosphere,
+            },
+        );
+        let composite = render_pipelines.specialize(
+            &pipeline_cache,
+            &composite_pipeline,
+            CloudsCompositePipelineKey {
+                texture_format: view_target.main_texture_format(),
+                msaa_samples: msaa.samples(),
+                atmosphere,
+            },
+        );
+        commands.entity(entity).insert(CloudsViewPipelines {
+            update,
+            composite,
+            atmosphere,
+        });
+    }
 }
 
-impl Material for CloudsMaterial {
-    fn fragment_shader() -> ShaderRef {
-        ShaderRef::Path(
-            AssetPath::from_path_buf(embedded_path!("shaders/clouds.wgsl")).with_source("embedded"),
+/// The bind groups which depend on the depth prepass of a view.
+#[derive(Component)]
+pub(crate) struct CloudsViewBindGroups {
+    depth: BindGroup,
+    composite: BindGroup,
+}
+
+#[expect(clippy::too_many_arguments)]
+fn prepare_clouds_view_bind_groups(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    clouds_pipeline: Res<CloudsPipeline>,
+    composite_pipeline: Res<CloudsCompositePipeline>,
+    clouds_uniform_buffer: Res<CloudsUniformBuffer>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    render_device: Res<RenderDevice>,
+    views: Query<(Entity, &ViewPrepassTextures, &Msaa, &CloudsRenderImages)>,
+) {
+    let Some(uniform_binding) = clouds_uniform_buffer.buffer.binding() else {
+        return;
+    };
+
+    for (entity, prepass_textures, msaa, render_images) in &views {
+        let Some(depth_view) = prepass_textures.depth_view() else {
+            continue;
+        };
+        let Some(cloud_render_view) = gpu_images.get(&render_images.cloud_render_image) else {
+            continue;
+        };
+        let Some(cloud_depth_view) = gpu_images.get(&render_images.cloud_depth_image) else {
+            continue;
+        };
+        let Some(sky_view) = gpu_images.get(&render_images.sky_image) else {
+            continue;
+        };
+        let (depth_layout, composite_layout) = if msaa.samples() > 1 {
+            (
+                &clouds_pipeline.depth_bind_group_layout_multisampled,
+                &composite_pipeline.bind_group_layout_multisampled,
+            )
+        } else {
+            (
+                &clouds_pipeline.depth_bind_group_layout,
+                &composite_pipeline.bind_group_layout,
+            )
+        };
+
+        let depth = render_device.create_bind_group(
+            "clouds_depth_bind_group",
+            &pipeline_cache.get_bind_group_layout(depth_layout),
+            &BindGroupEntries::single(depth_view),
+        );
+        let composite = render_device.create_bind_group(
+            "clouds_composite_bind_group",
+            &pipeline_cache.get_bind_group_layout(composite_layout),
+            &BindGroupEntries::sequential((
+                uniform_binding.clone(),
+                &cloud_render_view.texture_view,
+                &cloud_depth_view.texture_view,
+                &sky_view.texture_view,
+                depth_view,
+            )),
+        );
+        commands
+            .entity(entity)
+            .insert(CloudsViewBindGroups { depth, composite });
+    }
+}
+
+/// Renders the clouds of a view and composites them over the opaque scene, limiting the raymarch
+/// to the depth of the scene.
+#[derive(Default)]
+pub(crate) struct CloudsRenderNode;
+
+impl ViewNode for CloudsRenderNode {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static ViewTarget,
+        &'static VolumetricClouds,
+        &'static CloudsViewPipelines,
+        &'static CloudsViewImages,
+        &'static CloudsViewBindGroups,
+        &'static CloudsUniformOffset,
+        Option<&'static CloudsAtmosphereBindGroup>,
+    );
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (
+            camera,
+            view_target,
+            volumetric_clouds,
+            view_pipelines,
+            view_images,
+            view_bind_groups,
+            uniform_offset,
+            atmosphere_bind_group,
+        ): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        // The noise textures are uninitialized until the noise has been generated or loaded.
+        if !world.contains_resource::<CloudsNoiseReady>() {
+            return Ok(());
+        }
+        let Some(noise_bind_group) = world.get_resource::<CloudsNoiseBindGroup>() else {
+            return Ok(());
+        };
+        let Some(uniform_bind_group) = world.get_resource::<CloudsUniformBindGroup>() else {
+            return Ok(());
+        };
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let clouds_pipeline = world.resource::<CloudsPipeline>();
+
+        let (
+            Some(update_pipeline),
+            Some(interpolate_pipeline),
+            Some(resolve_pipeline),
+            Some(composite_pipeline),
+        ) = (
+            pipeline_cache.get_compute_pipeline(view_pipelines.update),
+            pipeline_cache.get_compute_pipeline(clouds_pipeline.interpolate_pipeline),
+            pipeline_cache.get_compute_pipeline(clouds_pipeline.resolve_pipeline),
+            pipeline_cache.get_render_pipeline(view_pipelines.composite),
         )
+        else {
+            return Ok(());
+        };
+        // The LUTs of the atmosphere of a view are only available from its second frame on.
+        if view_pipelines.atmosphere && atmosphere_bind_group.is_none() {
+            return Ok(());
+        }
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor::default());
+
+        pass.set_bind_group(0, &uniform_bind_group.0, &[uniform_offset.0]);
+        pass.set_bind_group(1, &noise_bind_group.0, &[]);
+        pass.set_bind_group(2, &view_images.bind_group, &[]);
+        pass.set_bind_group(3, &view_bind_groups.depth, &[]);
+        if view_pipelines.atmosphere
+            && let Some(atmosphere) = atmosphere_bind_group
+        {
+            pass.set_bind_group(4, &atmosphere.bind_group, &[atmosphere.uniform_offset]);
+        }
+
+        let render_size = view_images.render_size;
+
+        // Only one pixel per tile gets raymarched, see `Interleaving`.
+        let tile_size = volumetric_clouds
+            .apply_to(*world.resource::<CloudsConfig>())
+            .interleaving
+            .tile_size();
+        let traced_size = (render_size + tile_size - 1) / tile_size;
+        pass.set_pipeline(update_pipeline);
+        pass.dispatch_workgroups(
+            traced_size.x.div_ceil(WORKGROUP_SIZE),
+            traced_size.y.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+
+        for pipeline in [interpolate_pipeline, resolve_pipeline] {
+            pass.set_pipeline(pipeline);
+            pass.dispatch_workgroups(
+                render_size.x.div_ceil(WORKGROUP_SIZE),
+                render_size.y.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+        drop(pass);
+
+        for (source, destination) in &view_images.history_copies {
+            render_context.command_encoder().copy_texture_to_texture(
+                source.as_image_copy(),
+                destination.as_image_copy(),
+                source.size(),
+            );
+        }
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("clouds_composite_pass"),
+            color_attachments: &[Some(view_target.get_color_attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        if let Some(viewport) = camera.viewport.as_ref() {
+            render_pass.set_camera_viewport(viewport);
+        }
+        render_pass.set_render_pipeline(composite_pipeline);
+        render_pass.set_bind_group(0, &view_bind_groups.composite, &[uniform_offset.0]);
+        render_pass.draw(0..3, 0..1);
+
+        Ok(())
     }
 }
 
-pub(crate) struct CloudsShaderPlugin;
+/// A plugin which renders the clouds of each view with [`VolumetricClouds`] and composites them
+/// over the opaque scene, before transparent objects and tonemapping.
+pub(crate) struct CloudsRenderPlugin;
 
-impl Plugin for CloudsShaderPlugin {
+impl Plugin for CloudsRenderPlugin {
     fn build(&self, app: &mut App) {
-        load_shader_library!(app, "shaders/common.wgsl");
-
-        embedded_asset!(app, "shaders/clouds.wgsl");
-        embedded_asset!(app, "shaders/clouds_compute.wgsl");
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_systems(
+                Render,
+                (
+                    queue_clouds_pipelines.in_set(RenderSystems::Queue),
+                    prepare_clouds_view_bind_groups.in_set(RenderSystems::PrepareBindGroups),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<CloudsRenderNode>>(Core3d, CloudsLabel::Render)
+            .add_render_graph_edges(
+                Core3d,
+                (
+                    Node3d::MainOpaquePass,
+                    CloudsLabel::Render,
+                    Node3d::MainTransmissivePass,
+                ),
+            );
+    }
 
-        app.add_plugins(MaterialPlugin::<CloudsMaterial>::default());
+    fn finish(&self, app: &mut App) {
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.init_resource::<CloudsCompositePipeline>();
+        render_app.init_resource::<SpecializedRenderPipelines<CloudsCompositePipeline>>();
     }
 }
@@ -0,0 +1,162 @@
+#define_import_path bevy_open_world::atmosphere
+
+// Reads the LUTs of Bevy's `Atmosphere`, for views that have one. The parameterizations of the LUTs
+// match `bevy_pbr::atmosphere::functions`, which can't be imported because its bindings overlap
+// with the bindings of the clouds.
+
+const PI: f32 = 3.141592653589793;
+const HALF_PI: f32 = 1.5707963267948966;
+const INV_TAU: f32 = 0.15915494309189535;
+const EPSILON: f32 = 1.0e-4;
+
+struct Atmosphere {
+    // Rotates directions in the world into the space of the sky-view LUT.
+    atmosphere_from_world: mat4x4f,
+    ground_albedo: vec3f,
+    bottom_radius: f32,
+    top_radius: f32,
+    scene_units_to_m: f32,
+    sky_view_lut_size: vec2f,
+    // Color times illuminance of the sun, in lux.
+    sun_illuminance: vec3f,
+    exposure: f32,
+    // Color times illuminance of the full moon, in lux.
+    moon_illuminance: vec3f,
+}
+
+@group(4) @binding(0) var<uniform> atmosphere: Atmosphere;
+@group(4) @binding(1) var transmittance_lut: texture_2d<f32>;
+@group(4) @binding(2) var sky_view_lut: texture_2d<f32>;
+@group(4) @binding(3) var lut_sampler: sampler;
+
+// Position of a point in the scene relative to the center of the planet, in meters. Like Bevy's
+// atmosphere, the origin of the scene lies on the surface of the planet.
+fn position_world_to_atmosphere(world_position: vec3f) -> vec3f {
+    let position = world_position * atmosphere.scene_units_to_m +
+        vec3f(0.0, atmosphere.bottom_radius, 0.0);
+    let r = max(length(position), atmosphere.bottom_radius + EPSILON);
+    return normalize(position) * r;
+}
+
+fn distance_to_top_boundary(r: f32, mu: f32) -> f32 {
+    let discriminant = max(r * r * (mu * mu - 1.0) + atmosphere.top_radius * atmosphere.top_radius, 0.0);
+    return max(-r * mu + sqrt(discriminant), 0.0);
+}
+
+fn distance_to_bottom_boundary(r: f32, mu: f32) -> f32 {
+    let discriminant = max(r * r * (mu * mu - 1.0) + atmosphere.bottom_radius * atmosphere.bottom_radius, 0.0);
+    return max(-r * mu - sqrt(discriminant), 0.0);
+}
+
+fn ray_intersects_ground(r: f32, mu: f32) -> bool {
+    return mu < 0.0 &&
+        r * r * (mu * mu - 1.0) + atmosphere.bottom_radius * atmosphere.bottom_radius >= 0.0;
+}
+
+// Distance to the edge of the atmosphere or to the ground, whichever the ray hits first.
+fn max_atmosphere_distance(r: f32, mu: f32) -> f32 {
+    if ray_intersects_ground(r, mu) {
+        return distance_to_bottom_boundary(r, mu);
+    }
+    return distance_to_top_boundary(r, mu);
+}
+
+// Transmittance from a point at radius `r` to the edge of the atmosphere, along a ray with cosine
+// `mu` of its zenith angle.
+fn sample_transmittance_lut(r: f32, mu: f32) -> vec3f {
+    let bottom_radius = atmosphere.bottom_radius;
+    let top_radius = atmosphere.top_radius;
+    let h = sqrt(top_radius * top_radius - bottom_radius * bottom_radius);
+    let rho = sqrt(max(r * r - bottom_radius * bottom_radius, 0.0));
+    let d = distance_to_top_boundary(r, mu);
+    let d_min = top_radius - r;
+    let d_max = rho + h;
+    let uv = vec2f((d - d_min) / (d_max - d_min), rho / h);
+    return textureSampleLevel(transmittance_lut, lut_sampler, uv, 0.0).rgb;
+}
+
+// Transmittance along the first `t` meters of a ray, following Eric Bruneton's
+// `GetTransmittance`, as in Bevy's `sample_transmittance_lut_segment`.
+fn sample_transmittance_lut_segment(r: f32, mu: f32, t: f32) -> vec3f {
+    let r_t = sqrt(t * t + 2.0 * r * mu * t + r * r);
+    let mu_t = clamp((r * mu + t) / r_t, -1.0, 1.0);
+
+    if ray_intersects_ground(r, mu) {
+        return min(sample_transmittance_lut(r_t, -mu_t) / sample_transmittance_lut(r, -mu), vec3f(1.0));
+    }
+    return min(sample_transmittance_lut(r, mu) / sample_transmittance_lut(r_t, mu_t), vec3f(1.0));
+}
+
+fn sample_sky_view_lut(r: f32, ray_dir: vec3f) -> vec3f {
+    let ray_dir_as = (atmosphere.atmosphere_from_world * vec4f(ray_dir, 0.0)).xyz;
+    let azimuth = atan2(ray_dir_as.x, -ray_dir_as.z);
+
+    // The texels are concentrated near the horizon.
+    let bottom_radius = atmosphere.bottom_radius;
+    let horizon_zenith = PI - acos(sqrt(max(r * r - bottom_radius * bottom_radius, 0.0)) / r);
+    let l = acos(clamp(ray_dir_as.y, -1.0, 1.0)) - horizon_zenith;
+    let v = 0.5 + 0.5 * sign(l) * sqrt(abs(l) / HALF_PI);
+
+    let size = atmosphere.sky_view_lut_size;
+    let uv = (vec2f(azimuth * INV_TAU + 0.5, v) + 0.5 / size) * (size / (size + 1.0));
+    return textureSampleLevel(sky_view_lut, lut_sampler, uv, 0.0).rgb;
+}
+
+// The light scattered towards the camera by the air along `ray_dir`, without the sun disk. Like
+// the rest of the scene, it is multiplied by the exposure of the camera.
+fn sky_radiance(camera_position: vec3f, ray_dir: vec3f) -> vec3f {
+    let r = length(position_world_to_atmosphere(camera_position));
+    return sample_sky_view_lut(r, ray_dir) * atmosphere.exposure;
+}
+
+// The light of a directional light with the given illuminance that reaches a point in the scene
+// through the atmosphere, multiplied by the exposure of the camera. `light_dir` points towards the
+// light.
+fn light_illuminance(world_position: vec3f, light_dir: vec3f, illuminance: vec3f) -> vec3f {
+    let position = position_world_to_atmosphere(world_position);
+    let r = length(position);
+    let mu = dot(position / r, light_dir);
+    if ray_intersects_ground(r, mu) {
+        return vec3f(0.0);
+    }
+    return illuminance * sample_transmittance_lut(r, mu) * atmosphere.exposure;
+}
+
+// The sunlight that reaches a point in the scene, see `light_illuminance`.
+fn sun_illuminance(world_position: vec3f, sun_dir: vec3f) -> vec3f {
+    return light_illuminance(world_position, sun_dir, atmosphere.sun_illuminance);
+}
+
+// The light of the full moon that reaches a point in the scene, see `light_illuminance`.
+fn moon_illuminance(world_position: vec3f, moon_dir: vec3f) -> vec3f {
+    return light_illuminance(world_position, moon_dir, atmosphere.moon_illuminance);
+}
+
+struct AerialPerspective {
+    // Light scattered towards the camera by the air in front of the point.
+    inscattering: vec3f,
+    // Transmittance of the air in front of the point.
+    transmittance: vec3f,
+}
+
+// The effect of the air between the camera and the point at distance `t` along `ray_dir`.
+//
+// Bevy's aerial-view LUT only covers the first 32 km in front of the camera, while clouds are
+// often further away. Instead, the inscattering of the whole ray is taken from the sky-view LUT
+// and divided according to the transmittance, assuming the light scattered along the ray follows
+// the density of the air.
+fn aerial_perspective(camera_position: vec3f, ray_dir: vec3f, t: f32) -> AerialPerspective {
+    let position = position_world_to_atmosphere(camera_position);
+    let r = length(position);
+    let mu = dot(position / r, ray_dir);
+    let t_max = max_atmosphere_distance(r, mu);
+    let transmittance = sample_transmittance_lut_segment(
+        r,
+        mu,
+        min(t * atmosphere.scene_units_to_m, t_max)
+    );
+    let total_transmittance = sample_transmittance_lut_segment(r, mu, t_max);
+    let fraction = saturate((1.0 - transmittance) / max(1.0 - total_transmittance, vec3f(EPSILON)));
+
+    return AerialPerspective(sky_radiance(camera_position, ray_dir) * fraction, transmittance);
+}
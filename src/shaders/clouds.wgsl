@@ -1,33 +1,140 @@
-#import bevy_sprite::{
-    mesh2d_view_bindings::viewport,
-    mesh2d_view_bindings::globals,
-    mesh2d_functions::{get_world_from_local, mesh2d_position_local_to_clip},
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_open_world::config::{config, scene_distance}
+
+@group(0) @binding(1) var clouds_render_texture: texture_2d<f32>;
+@group(0) @binding(2) var clouds_depth_texture: texture_2d<f32>;
+@group(0) @binding(3) var sky_texture: texture_2d<f32>;
+
+#ifdef MULTISAMPLED
+@group(0) @binding(4) var depth_texture: texture_depth_multisampled_2d;
+#else
+@group(0) @binding(4) var depth_texture: texture_depth_2d;
+#endif
+
+// How strongly differences in distance reduce the weight of a texel while upsampling.
+const DEPTH_SHARPNESS = 16.0;
+
+fn depth_weight(depth: f32, reference_depth: f32) -> f32 {
+    let relative_difference = abs(depth - reference_depth) / max(max(depth, reference_depth), 1.0);
+    return exp(-DEPTH_SHARPNESS * relative_difference);
+}
+
+// Distance to the opaque geometry that limited the raymarch of a texel of the clouds, this has to
+// match `get_scene_distance` in `clouds_compute.wgsl`.
+fn get_texel_scene_distance(texel: vec2i) -> f32 {
+    let uv = (vec2f(texel) + 0.5) / config.render_resolution;
+    let pixel = vec2u(config.viewport.xy + uv * config.viewport.zw);
+    return scene_distance(uv, textureLoad(depth_texture, pixel, 0));
 }
-#import bevy_pbr::{
-    mesh_view_bindings::view,
-    utils::coords_to_viewport_uv,
+
+struct Upsampled {
+    clouds: vec4f,
+    sky: vec3f,
 }
 
-#import bevy_sprite::mesh2d_vertex_output::VertexOutput
+// The clouds may be rendered at a lower resolution than the screen, see `RenderScale`.
+// Bilinear upsampling would blur the silhouettes of the clouds against the sky and let clouds
+// bleed over the edges of the geometry in front of them. So the bilinear weights are combined
+// with the similarity of the distance to the scene and to the clouds of each texel.
+fn upsample_clouds(viewport_uv: vec2f, pixel_scene_distance: f32) -> Upsampled {
+    let size = vec2i(config.render_resolution);
+    let max_texel = size - 1;
+    let coord = viewport_uv * vec2f(size) - 0.5;
+    let base = floor(coord);
+    let f = coord - base;
+
+    let nearest_texel = clamp(vec2i(round(coord)), vec2i(0), max_texel);
+    let reference_depth = textureLoad(clouds_depth_texture, nearest_texel, 0).r;
+
+    var clouds = vec4f(0.0);
+    var sky = vec3f(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < 4u; i++) {
+        let offset = vec2f(f32(i & 1u), f32(i >> 1u));
+        let texel = clamp(vec2i(base + offset), vec2i(0), max_texel);
+        let bilinear = mix(1.0 - f, f, offset);
+        let depth = textureLoad(clouds_depth_texture, texel, 0).r;
+        let weight = bilinear.x * bilinear.y *
+            depth_weight(depth, reference_depth) *
+            depth_weight(get_texel_scene_distance(texel), pixel_scene_distance);
 
-@group(3) @binding(100) var clouds_render_texture: texture_2d<f32>;
-@group(3) @binding(101) var clouds_render_sampler: sampler;
+        clouds += textureLoad(clouds_render_texture, texel, 0) * weight;
+        sky += textureLoad(sky_texture, texel, 0).rgb * bilinear.x * bilinear.y;
+        total_weight += weight;
+    }
+    return Upsampled(clouds / max(total_weight, 1.0e-6), sky);
+}
 
-@group(1) @binding(102) var clouds_atlas_texture: texture_2d<f32>;
-@group(1) @binding(103) var clouds_atlas_sampler: sampler;
+// Exponent of the cosine of the angle between a pixel and the sun, the higher the more the
+// crepuscular rays fade away from the sun.
+const CREPUSCULAR_RAYS_FALLOFF = 8.0;
 
-@group(1) @binding(104) var clouds_worley_texture: texture_3d<f32>;
-@group(1) @binding(105) var clouds_worley_sampler: sampler;
+// The direction of the view ray through `viewport_uv`, like `get_ray_direction` in
+// `clouds_compute.wgsl`.
+fn get_ray_direction(viewport_uv: vec2f) -> vec3f {
+    let ndc_xy = (viewport_uv * 2.0 - 1.0) * vec2f(1.0, -1.0);
+    let ray_eye = config.inverse_camera_projection * vec4f(ndc_xy, -1.0, 1.0);
+    let ray_world = config.inverse_camera_view * vec4f(ray_eye.xy, -1.0, 0.0);
+    return normalize(ray_world.xyz);
+}
 
-@group(3) @binding(106) var sky_texture: texture_2d<f32>;
-@group(3) @binding(107) var sky_sampler: sampler;
+// The fraction of the sunlight that reaches the camera along the line from `viewport_uv` towards
+// the sun on the screen, which gives the crepuscular rays through the gaps between the clouds. Only
+// the sky behind the clouds lets the light through, so the opaque scene blocks the rays as well.
+// The light is scattered mostly forward, so the rays fade with the angle between the pixel and the
+// sun.
+fn get_crepuscular_rays(viewport_uv: vec2f) -> f32 {
+    if config.crepuscular_rays_intensity <= 0.0 {
+        return 0.0;
+    }
+    // The sun is infinitely far away, so only the rotation of the view moves it on the screen.
+    let sun_clip = config.clip_from_world * vec4f(config.sun_dir.xyz, 0.0);
+    if sun_clip.w <= 0.0 {
+        return 0.0;
+    }
+    let sun_uv = sun_clip.xy / sun_clip.w * vec2f(0.5, -0.5) + 0.5;
 
+    let samples = max(config.crepuscular_rays_samples, 1u);
+    let step = (sun_uv - viewport_uv) / f32(samples);
+    let max_texel = vec2i(config.render_resolution) - 1;
+    var uv = viewport_uv;
+    var decay = 1.0;
+    var light = 0.0;
+    for (var i = 0u; i < samples; i++) {
+        uv += step;
+        if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) {
+            break;
+        }
+        let texel = clamp(vec2i(uv * config.render_resolution), vec2i(0), max_texel);
+        let pixel = vec2u(config.viewport.xy + uv * config.viewport.zw);
+        if textureLoad(depth_texture, pixel, 0) <= 0.0 {
+            light += textureLoad(clouds_render_texture, texel, 0).a * decay;
+        }
+        decay *= config.crepuscular_rays_decay;
+    }
+    let cos_angle = saturate(dot(get_ray_direction(viewport_uv), config.sun_dir.xyz));
+    return light / f32(samples) * pow(cos_angle, CREPUSCULAR_RAYS_FALLOFF);
+}
 
+// Composite the clouds over the opaque scene. The pipeline blends the output as
+// `output.rgb + scene * output.a`, so the sky replaces the background where there is no geometry.
+// Views with Bevy's `Atmosphere` already have its sky in the background, which is kept.
+//
+// The pass is limited to the camera's viewport, so `in.uv` is relative to the viewport while
+// `in.position` is relative to the render target, like the depth prepass.
 @fragment
-fn fragment(mesh: VertexOutput) -> @location(0) vec4<f32> {
-    let viewport_uv = coords_to_viewport_uv(mesh.position.xy, view.viewport);
-    let clouds = textureSampleLevel(clouds_render_texture, clouds_render_sampler, vec2(viewport_uv), 0.0);
-    let sky = textureSampleLevel(sky_texture, sky_sampler, vec2(viewport_uv), 0.0);
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
+    let pixel = vec2u(in.position.xy);
+    let depth = textureLoad(depth_texture, pixel, 0);
+    let upsampled = upsample_clouds(in.uv, scene_distance(in.uv, depth));
+    let rays = config.sun_color.rgb * config.crepuscular_rays_intensity *
+        get_crepuscular_rays(in.uv);
+    let clouds = vec4f(upsampled.clouds.rgb + rays, upsampled.clouds.a);
 
-    return vec4(clouds.rgb + sky.rgb * clouds.a, 1.0);
+#ifndef ATMOSPHERE
+    if depth <= 0.0 {
+        return vec4f(clouds.rgb + upsampled.sky * clouds.a, 0.0);
+    }
+#endif
+    return clouds;
 }
@@ -1,53 +1,63 @@
 #import bevy_open_world::common
+#import bevy_open_world::config::{
+    config, CloudLayer, CLOUD_LAYER_FLAT, CLOUD_LAYER_VOLUMETRIC, MAX_CLOUD_LAYERS, MAX_DISTANCE,
+    scene_distance
+}
+// Bevy's atmosphere of the view, only used by `update` for views with an `Atmosphere`.
+#ifdef ATMOSPHERE
+#import bevy_open_world::atmosphere
+#endif
 
 const EPSILON = 0.000001;
-const MAX_DISTANCE = 1.0e9;
-const WORLEY_RESOLUTION = 32;
-const WORLEY_RESOLUTION_F32 = 32.0;
-
-struct Config {
-    clouds_base_scale: f32,
-    clouds_raymarch_steps_count: u32,
-    clouds_bottom_height: f32,
-    clouds_top_height: f32,
-    clouds_coverage: f32,
-    clouds_density: f32,
-    clouds_detail_scale: f32,
-    clouds_detail_strength: f32,
-    clouds_base_edge_softness: f32,
-    clouds_bottom_softness: f32,
-    clouds_shadow_raymarch_steps_count: u32,
-    clouds_shadow_raymarch_step_size: f32,
-    clouds_shadow_raymarch_step_multiply: f32,
-    clouds_ambient_color_top: vec4f,
-    clouds_ambient_color_bottom: vec4f,
-    clouds_min_transmittance: f32,
-    planet_radius: f32,
-    forward_scattering_g: f32,
-    backward_scattering_g: f32,
-    scattering_lerp: f32,
-    sun_dir: vec4f,
-    sun_color: vec4f,
-    camera_translation: vec3f,
-    time: f32,
-    reprojection_strength: f32,
-    render_resolution: vec2f,
-    inverse_camera_view: mat4x4f,
-    inverse_camera_projection: mat4x4f,
-    wind_displacement: vec3f,
-};
-
-@group(0) @binding(0) var<uniform> config: Config;
-
-@group(1) @binding(0) var clouds_render_texture: texture_storage_2d<rgba32float, read_write>;
-@group(1) @binding(1) var clouds_atlas_texture: texture_storage_2d<rgba32float, read_write>;
-@group(1) @binding(2) var clouds_worley_texture: texture_storage_3d<rgba32float, read_write>;
-@group(1) @binding(3) var sky_texture: texture_storage_2d<rgba32float, read_write>;
+const PI = 3.141592653589793;
+
+@group(1) @binding(0) var clouds_atlas_texture: texture_2d<f32>;
+@group(1) @binding(1) var clouds_worley_texture: texture_3d<f32>;
+// Filters the Worley noise trilinearly between its mip levels.
+@group(1) @binding(6) var clouds_worley_sampler: sampler;
+// Horizontal directions which distort the details of the clouds, see `render_curl_noise`.
+@group(1) @binding(7) var clouds_curl_texture: texture_2d<f32>;
+@group(1) @binding(8) var clouds_curl_sampler: sampler;
+// The weather map, see `CloudsWeatherMap`.
+@group(1) @binding(2) var weather_map_texture: texture_2d<f32>;
+@group(1) @binding(3) var weather_map_sampler: sampler;
+// The vertical density profiles of the types of clouds, with a row per profile.
+@group(1) @binding(4) var cloud_profiles_texture: texture_2d<f32>;
+@group(1) @binding(5) var cloud_profiles_sampler: sampler;
+
+// The format of the render textures falls back to rgba32float, see `CloudsTextureFormats`.
+#ifdef RENDER_FORMAT_RGBA32FLOAT
+@group(2) @binding(0) var clouds_render_texture: texture_storage_2d<rgba32float, read_write>;
+@group(2) @binding(1) var sky_texture: texture_storage_2d<rgba32float, read_write>;
+@group(2) @binding(3) var clouds_raymarch_texture: texture_storage_2d<rgba32float, read_write>;
+@group(2) @binding(4) var clouds_history_texture: texture_storage_2d<rgba32float, read_write>;
+#else
+@group(2) @binding(0) var clouds_render_texture: texture_storage_2d<rgba16float, read_write>;
+@group(2) @binding(1) var sky_texture: texture_storage_2d<rgba16float, read_write>;
+@group(2) @binding(3) var clouds_raymarch_texture: texture_storage_2d<rgba16float, read_write>;
+@group(2) @binding(4) var clouds_history_texture: texture_storage_2d<rgba16float, read_write>;
+#endif
+@group(2) @binding(2) var clouds_depth_texture: texture_storage_2d<r32float, read_write>;
+@group(2) @binding(5) var clouds_history_depth_texture: texture_storage_2d<r32float, read_write>;
+
+// A face of the cubemap of an environment map, only used by `environment_map`.
+@group(2) @binding(6) var environment_map_texture: texture_storage_2d<rgba16float, write>;
+
+// The shadow map of the clouds, only used by `shadow_map`.
+@group(2) @binding(7) var shadow_map_texture: texture_storage_2d<rgba16float, write>;
+
+// The depth prepass of the view, only used by `update`.
+#ifdef MULTISAMPLED
+@group(3) @binding(0) var depth_texture: texture_depth_multisampled_2d;
+#else
+@group(3) @binding(0) var depth_texture: texture_depth_2d;
+#endif
 
 struct Ray {
     step_distance: f32,
     dir_length: f32,
-    start: f32
+    start: f32,
+    end: f32,
 }
 
 struct RaymarchResult {
@@ -55,94 +65,224 @@ struct RaymarchResult {
     color: vec4f,
 }
 
-fn cloud_map_base(p: vec3f, normalized_height: f32) -> f32 {
-	let uv = abs(p * (0.00005 * config.clouds_base_scale) * config.render_resolution.xyy);
+// The main layer of `CloudsConfig`, which comes before the `CloudsLayers`.
+fn main_layer() -> CloudLayer {
+    return CloudLayer(
+        config.wind_displacement,
+        config.previous_wind_displacement,
+        config.clouds_bottom_height,
+        config.clouds_top_height,
+        config.clouds_coverage,
+        config.clouds_density,
+        config.clouds_base_scale,
+        config.clouds_detail_scale,
+        config.cloud_type,
+        CLOUD_LAYER_VOLUMETRIC,
+        1u,
+    );
+}
+
+fn get_layer_count() -> u32 {
+    return min(config.layer_count, MAX_CLOUD_LAYERS) + 1u;
+}
+
+fn get_layer(index: u32) -> CloudLayer {
+    if index == 0u {
+        return main_layer();
+    }
+    return config.layers[index - 1u];
+}
+
+fn cloud_map_base(p: vec3f, normalized_height: f32, layer: CloudLayer) -> f32 {
+    let atlas_resolution = textureDimensions(clouds_atlas_texture);
+	let uv = abs(p * (0.00005 * layer.base_scale) * vec2f(atlas_resolution).xyy);
     let cloud = textureLoad(
         clouds_atlas_texture,
          vec2u(
-            u32(uv.x) % u32(config.render_resolution.x),
-            u32(uv.z) % u32(config.render_resolution.y)
-        )
+            u32(uv.x) % atlas_resolution.x,
+            u32(uv.z) % atlas_resolution.y
+        ),
+        0
     ).rgb;
 
     var n = normalized_height * normalized_height * cloud.b + pow(1.0 - normalized_height, 16.0);
-	return common::remap(cloud.r - n, cloud.g, 1.0);
+	return common::remap(cloud.r - n, cloud.g - 1.0, 1.0);
+}
+
+// The Worley noise repeats every this many units of the coordinates of the detail noise, whatever
+// the size of its volume.
+const DETAIL_NOISE_PERIOD = 32.0;
+
+fn get_detail_frequency(layer: CloudLayer) -> f32 {
+    return 0.0016 * layer.base_scale * layer.detail_scale;
 }
 
-fn cloud_map_detail(position: vec3f) -> f32 {
-    let p = abs(position) * (0.0016 * config.clouds_base_scale * config.clouds_detail_scale);
+// The mip level of the Worley noise for samples which are `footprint` apart, such that neighbouring
+// samples are about a texel apart.
+fn get_detail_lod(footprint: f32, layer: CloudLayer) -> f32 {
+    let texels_per_period = f32(textureDimensions(clouds_worley_texture).x);
+    let texels = footprint * get_detail_frequency(layer) * texels_per_period / DETAIL_NOISE_PERIOD;
+    return max(log2(texels), 0.0);
+}
 
-    // TODO: add bilinear filtering
-    var p1 = p % 32.0;
-    let a = textureLoad(clouds_worley_texture, vec3u(u32(p1.x), u32(p1.y), u32(p1.z))).r;
+// The size of a pixel of the view at `distance` from the camera.
+fn get_pixel_footprint(distance: f32) -> f32 {
+    return distance * 2.0 * config.inverse_camera_projection[1][1] / config.render_resolution.y;
+}
 
-    // TODO: add bilinear filtering
-    let p2 = (p + 1.0) % 32.0;
-    let b = textureLoad(clouds_worley_texture, vec3u(u32(p2.x), u32(p2.y), u32(p2.z))).r;
+fn cloud_map_detail(position: vec3f, layer: CloudLayer, lod: f32) -> f32 {
+    let p = abs(position) * get_detail_frequency(layer);
+    return textureSampleLevel(
+        clouds_worley_texture,
+        clouds_worley_sampler,
+        p / DETAIL_NOISE_PERIOD,
+        lod
+    ).r;
+}
 
-    return mix(a, b, fract(p.y));
+// Erode a bit from the clouds_bottom_height and clouds_top_height of the cloud layer, following
+// the profile of `cloud_type` in `CloudsProfiles` which blends the neighbouring profiles.
+fn cloud_gradient(normalized_height: f32, cloud_type: f32) -> f32 {
+    // Sample at the centers of the first and last texels, such that a type of 0 and 1 select the
+    // first and last profile.
+    let size = vec2f(textureDimensions(cloud_profiles_texture));
+    let coords = vec2f(saturate(normalized_height), saturate(cloud_type));
+    let uv = (coords * (size - 1.0) + 0.5) / size;
+    return textureSampleLevel(cloud_profiles_texture, cloud_profiles_sampler, uv, 0.0).r;
 }
 
-// Erode a bit from the clouds_bottom_height and clouds_top_height of the cloud layer
-fn cloud_gradient(normalized_height: f32) -> f32 {
-    return (
-        common::linearstep(0.0, 0.1, normalized_height) -
-        common::linearstep(0.8, 1.2, normalized_height)
-    );
+// How much denser wet clouds are than dry clouds.
+const WET_DENSITY = 3.0;
+
+struct Weather {
+    coverage: f32,
+    cloud_type: f32,
+    wetness: f32,
+}
+
+// The weather at a point in the space of the raymarch of a layer, from the weather map if there is
+// one and the layer uses it.
+fn get_weather(pos: vec3f, layer: CloudLayer) -> Weather {
+    if layer.use_weather_map == 0u || any(config.weather_map_extent <= vec2f(0.0)) {
+        return Weather(layer.coverage, layer.cloud_type, 0.0);
+    }
+
+    // The weather map doesn't move with the wind.
+    let world_position = get_world_position(pos, layer);
+    let uv = (world_position.xz - config.weather_map_center) / config.weather_map_extent + 0.5;
+    let weather = textureSampleLevel(weather_map_texture, weather_map_sampler, uv, 0.0);
+    return Weather(weather.r, weather.g, weather.b);
+}
+
+// Offset of the detail noise along the curl noise, which fades out towards the top of the layer.
+// This turns the erosion at the bottom of the clouds into wispy billows.
+fn get_curl_offset(position: vec3f, normalized_height: f32, layer: CloudLayer) -> vec3f {
+    let uv = position.xz * (0.00005 * layer.base_scale * config.clouds_curl_scale);
+    let curl = textureSampleLevel(clouds_curl_texture, clouds_curl_sampler, uv, 0.0).rg * 2.0 - 1.0;
+    return vec3f(curl.x, 0.0, curl.y) * config.clouds_curl_strength * (1.0 - normalized_height);
 }
 
-fn get_cloud_map_density(pos: vec3f, normalized_height: f32) -> f32 {
+// `lod` is the mip level of the detail noise, see `get_detail_lod`.
+fn get_cloud_map_density(pos: vec3f, normalized_height: f32, layer: CloudLayer, lod: f32) -> f32 {
     let ps = pos;
+    let weather = get_weather(ps, layer);
 
-    var m = cloud_map_base(ps, normalized_height) * cloud_gradient(normalized_height);
+    var m = cloud_map_base(ps, normalized_height, layer) * cloud_gradient(normalized_height, weather.cloud_type);
 
 	let clouds_detail_strength = smoothstep(1.0, 0.5, m);
 
     // Erode with detail
     if clouds_detail_strength > 0.0 {
-		m -= cloud_map_detail(ps) * clouds_detail_strength * config.clouds_detail_strength;
+        let detail_position = ps + get_curl_offset(ps, normalized_height, layer);
+		m -= cloud_map_detail(detail_position, layer, lod) * clouds_detail_strength * config.clouds_detail_strength;
     }
 
-	m = smoothstep(0.0, config.clouds_base_edge_softness, m + config.clouds_coverage - 1.0);
+	m = smoothstep(0.0, config.clouds_base_edge_softness, m + weather.coverage - 1.0);
     m *= common::linearstep0(config.clouds_bottom_softness, normalized_height);
+    m *= mix(1.0, WET_DENSITY, weather.wetness);
 
-    return clamp(m * config.clouds_density * (1.0 + max((ps.x - 7000.0) * 0.005, 0.0)), 0.0, 1.0);
+    return clamp(m * layer.density * (1.0 + max((ps.x - 7000.0) * 0.005, 0.0)), 0.0, 1.0);
 }
 
-fn get_normalized_height(pos: vec3f) -> f32 {
-    let clouds_height = config.clouds_top_height - config.clouds_bottom_height;
-    return (length(pos) - (config.planet_radius + config.clouds_bottom_height)) / clouds_height;
+fn get_normalized_height(pos: vec3f, layer: CloudLayer) -> f32 {
+    let clouds_height = layer.top_height - layer.bottom_height;
+    return (length(pos) - (config.planet_radius + layer.bottom_height)) / clouds_height;
 }
 
-fn volumetric_shadow(origin: vec3f, ray_dot_sun: f32) -> f32 {
+// Optical depth of the clouds of a layer between a point and a light in direction `light_dir`.
+fn volumetric_shadow(origin: vec3f, light_dir: vec3f, layer: CloudLayer, lod: f32) -> f32 {
     var ray_step_size = config.clouds_shadow_raymarch_step_size;
     var distance_along_ray = ray_step_size * 0.5;
-    var transmittance = 1.0;
+    var optical_depth = 0.0;
 
     for (var step: u32 = 0; step < config.clouds_shadow_raymarch_steps_count; step++) {
-        let pos = origin + config.sun_dir.xyz * distance_along_ray;
-        let normalized_height = get_normalized_height(pos);
+        let pos = origin + light_dir * distance_along_ray;
+        let normalized_height = get_normalized_height(pos, layer);
 
-        if (normalized_height > 1.0) { return transmittance; };
+        if (normalized_height > 1.0) { return optical_depth; };
 
-        let clouds_density = get_cloud_map_density(pos, normalized_height);
-        transmittance *= exp(-clouds_density * ray_step_size);
+        let clouds_density = get_cloud_map_density(pos, normalized_height, layer, lod);
+        optical_depth += clouds_density * ray_step_size;
 
         ray_step_size *= config.clouds_shadow_raymarch_step_multiply;
         distance_along_ray += ray_step_size;
     }
 
-    return transmittance;
+    return optical_depth;
 }
 
-fn intersect_planet_sphere(ray_dir: vec3f, sample_radius: f32) -> f32 {
-    let clouds_bottom_height = config.planet_radius * ray_dir.y;
-    let d = (
-        clouds_bottom_height * clouds_bottom_height +
-        sample_radius * sample_radius +
-        2.0 * config.planet_radius * sample_radius
-    );
-    return sqrt(d) - clouds_bottom_height;
+// Distances along the ray to the near and far intersection with a sphere around the center of the
+// planet, or `MAX_DISTANCE` if the ray misses the sphere.
+fn intersect_sphere(ray_origin: vec3f, ray_dir: vec3f, radius: f32) -> vec2f {
+    let origin_radius = length(ray_origin);
+    let b = dot(ray_origin, ray_dir);
+    let c = (origin_radius - radius) * (origin_radius + radius);
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return vec2f(MAX_DISTANCE);
+    }
+    let root = sqrt(discriminant);
+    return vec2f(-b - root, -b + root);
+}
+
+// The scene position of a point in the space of the raymarch of a layer, which is centered at the
+// planet and moves along with the wind of the layer.
+fn get_world_position(position: vec3f, layer: CloudLayer) -> vec3f {
+    return position + layer.wind_displacement - vec3f(0.0, config.planet_radius, 0.0);
+}
+
+// The color of the sunlight that reaches a point in the clouds of a layer.
+fn get_sun_color(position: vec3f, layer: CloudLayer) -> vec3f {
+#ifdef ATMOSPHERE
+    // The phase function isn't normalized in `raymarch`, so the illuminance is divided by 4π.
+    let world_position = get_world_position(position, layer);
+    return atmosphere::sun_illuminance(world_position, config.sun_dir.xyz) / (4.0 * PI);
+#else
+    return config.sun_color.rgb;
+#endif
+}
+
+// The fraction of the disk of the moon that is lit by the sun.
+fn get_moon_phase() -> f32 {
+    return 0.5 - 0.5 * dot(config.sun_dir.xyz, config.moon_dir.xyz);
+}
+
+// The color of the moonlight that reaches a point in the clouds, which depends on the phase of the
+// moon. It fades out as the moon sets.
+fn get_moon_color(position: vec3f, layer: CloudLayer) -> vec3f {
+#ifdef ATMOSPHERE
+    let world_position = get_world_position(position, layer);
+    let moon_color = atmosphere::moon_illuminance(world_position, config.moon_dir.xyz) / (4.0 * PI);
+#else
+    let moon_color = config.moon_color.rgb * smoothstep(-0.02, 0.02, config.moon_dir.y);
+#endif
+    return moon_color * get_moon_phase();
+}
+
+// Whether the moonlight is worth its own shadow raymarch.
+fn is_moon_lighting() -> bool {
+    return any(config.moon_color.rgb > vec3f(0.0)) && config.moon_dir.y > -0.02 &&
+        get_moon_phase() > 0.0;
 }
 
 fn henyey_greenstein(ray_dot_sun: f32, g: f32) -> f32 {
@@ -150,42 +290,127 @@ fn henyey_greenstein(ray_dot_sun: f32, g: f32) -> f32 {
     return (1.0 - g_squared) / pow(1.0 + g_squared - 2.0 * g * ray_dot_sun, 1.5);
 }
 
-fn get_ray(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32) -> Ray {
-    var start = intersect_planet_sphere(ray_dir, config.clouds_bottom_height);
-    var end = intersect_planet_sphere(ray_dir, config.clouds_top_height);
-    var inside = intersect_planet_sphere(ray_dir, ray_origin.y - config.planet_radius);
-
-    if (start <= inside && inside <= end) {
-        if (ray_dir.y < 0.0) {
-            end = inside;
-        } else {
-            start = inside;
+// Find the part of the ray that lies within a cloud layer. The camera can be below, inside or
+// above the layer, the segments of these cases match at the boundaries of the layer such that
+// crossing them doesn't cause popping.
+fn get_ray(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32, layer: CloudLayer) -> Ray {
+    let bottom_radius = config.planet_radius + layer.bottom_height;
+    let top_radius = config.planet_radius + layer.top_height;
+    let origin_radius = length(ray_origin);
+    let bottom = intersect_sphere(ray_origin, ray_dir, bottom_radius);
+    let top = intersect_sphere(ray_origin, ray_dir, top_radius);
+
+    var start: f32;
+    var end: f32;
+    if origin_radius < bottom_radius {
+        // Below the layer: enter through the bottom and leave through the top, unless the ray
+        // hits the ground first.
+        start = bottom.y;
+        end = top.y;
+        let ground = intersect_sphere(ray_origin, ray_dir, config.planet_radius);
+        if origin_radius >= config.planet_radius && ground.x > 0.0 {
+            end = min(end, ground.x);
+        }
+    } else if origin_radius <= top_radius {
+        // Inside the layer: march from the camera until leaving through the bottom or the top.
+        start = 0.0;
+        end = top.y;
+        if bottom.x > 0.0 {
+            end = min(end, bottom.x);
+        }
+    } else {
+        // Above the layer: enter through the top and leave through the bottom or the top.
+        start = top.x;
+        end = top.y;
+        if bottom.x > 0.0 {
+            end = min(end, bottom.x);
         }
     }
 
+    start = max(start, 0.0);
     end = min(end, max_dist);
 
-    let step_distance = (end - start) / f32(config.clouds_raymarch_steps_count);
+    let step_distance = max(end - start, 0.0) / f32(config.clouds_raymarch_steps_count);
     let hashed_offset = common::hash13(ray_dir + fract(config.time));
-    var dir_length = start - step_distance * hashed_offset;
+    var dir_length = start + step_distance * hashed_offset;
+
+    return Ray(step_distance, dir_length, start, end);
+}
 
-    return Ray(step_distance, dir_length, start);
+// Frostbite: dual-lobe phase function
+fn get_scattering(ray_dir: vec3f, light_dir: vec3f) -> f32 {
+    return get_phase(dot(ray_dir, -light_dir), 1.0);
 }
 
-fn raymarch(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32) -> RaymarchResult {
-    let ray = get_ray(ray_origin, ray_dir, max_dist);
+// The mix of the forward and backward scattering lobes, with their eccentricity scaled by
+// `eccentricity`.
+fn get_phase(ray_dot_light: f32, eccentricity: f32) -> f32 {
+    return mix(
+        henyey_greenstein(ray_dot_light, config.forward_scattering_g * eccentricity),
+        henyey_greenstein(ray_dot_light, config.backward_scattering_g * eccentricity),
+        config.scattering_lerp
+    );
+}
 
-    if (ray.start > max_dist) {
+// The fraction of a light that gets scattered towards the camera by a point with `optical_depth` of
+// clouds towards the light. Multiple scattering is approximated by octaves of single scattering
+// with less extinction, contribution and eccentricity, see the Frostbite paper in the README.
+fn get_multiple_scattering(ray_dir: vec3f, light_dir: vec3f, optical_depth: f32) -> f32 {
+    let ray_dot_light = dot(ray_dir, -light_dir);
+    var attenuation = 1.0;
+    var contribution = 1.0;
+    var eccentricity = 1.0;
+    var scattering = 0.0;
+    for (var octave = 0u; octave < max(config.multiple_scattering_octaves, 1u); octave++) {
+        scattering += contribution * get_phase(ray_dot_light, eccentricity) *
+            exp(-attenuation * optical_depth);
+        attenuation *= config.multiple_scattering_attenuation;
+        contribution *= config.multiple_scattering_contribution;
+        eccentricity *= config.multiple_scattering_eccentricity;
+    }
+    return scattering;
+}
+
+// The "powder" effect of Horizon Zero Dawn: light only gets scattered towards the camera after it
+// got deeper into a cloud, so thin edges with little density are darker. Looking towards the light,
+// forward scattering lights up the edges instead, so the effect fades out.
+fn get_powder(density: f32, ray_dir: vec3f, light_dir: vec3f) -> f32 {
+    let powder = 1.0 - exp(-2.0 * density * config.clouds_shadow_raymarch_step_size);
+    let towards_light = saturate(dot(ray_dir, -light_dir));
+    return mix(1.0, mix(powder, 1.0, towards_light), config.powder_strength);
+}
+
+// The ambient light at the top of the clouds.
+fn get_ambient_top() -> vec3f {
+#ifdef ATMOSPHERE
+    // The clouds are lit by the sky above them and the ground below them.
+    return atmosphere::sky_radiance(config.camera_translation, vec3f(0.0, 1.0, 0.0));
+#else
+    return config.clouds_ambient_color_top.rgb;
+#endif
+}
+
+// The ambient light at the bottom of the clouds.
+fn get_ambient_bottom() -> vec3f {
+#ifdef ATMOSPHERE
+    return get_ambient_top() * atmosphere::atmosphere.ground_albedo;
+#else
+    return config.clouds_ambient_color_bottom.rgb;
+#endif
+}
+
+fn raymarch(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32, layer: CloudLayer) -> RaymarchResult {
+    let ray = get_ray(ray_origin, ray_dir, max_dist, layer);
+
+    if (ray.start >= ray.end) {
         return RaymarchResult(max_dist, vec4f(0.0, 0.0, 0.0, 1.0));
     }
 
-    // Frostbite: dual-lobe phase function
-    let ray_dot_sun = dot(ray_dir, -config.sun_dir.xyz);
-    let scattering = mix(
-        henyey_greenstein(ray_dot_sun, config.forward_scattering_g),
-        henyey_greenstein(ray_dot_sun, config.backward_scattering_g),
-        config.scattering_lerp
-    );
+    // The moon lights the clouds in the same way, at the cost of a second shadow raymarch.
+    let moon_lighting = is_moon_lighting();
+
+    let ambient_top = get_ambient_top();
+    let ambient_bottom = get_ambient_bottom();
 
     var dir_length = ray.dir_length;
     var dist = max_dist;
@@ -195,23 +420,28 @@ fn raymarch(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32) -> RaymarchResult
     for (var step: u32 = 0; step < config.clouds_raymarch_steps_count; step++) {
         let world_position = ray_origin + dir_length * ray_dir;
 
-        let normalized_height = clamp(get_normalized_height(world_position), 0.0, 1.0);
-        let clouds_density_sampled = get_cloud_map_density(world_position, normalized_height);
+        let normalized_height = clamp(get_normalized_height(world_position, layer), 0.0, 1.0);
+        let lod = get_detail_lod(get_pixel_footprint(dir_length), layer);
+        let clouds_density_sampled =
+            get_cloud_map_density(world_position, normalized_height, layer, lod);
 
         if (clouds_density_sampled > 0.0) {
             dist = min(dist, dir_length);
 
-            let ambient_light = mix(
-                config.clouds_ambient_color_bottom,
-                config.clouds_ambient_color_top,
-                normalized_height
-            );
+            let ambient_light = mix(ambient_bottom, ambient_top, normalized_height);
+
+            let sun_depth = volumetric_shadow(world_position, config.sun_dir.xyz, layer, lod);
+            var direct_light = get_sun_color(world_position, layer) *
+                get_multiple_scattering(ray_dir, config.sun_dir.xyz, sun_depth) *
+                get_powder(clouds_density_sampled, ray_dir, config.sun_dir.xyz);
+            if moon_lighting {
+                let moon_depth = volumetric_shadow(world_position, config.moon_dir.xyz, layer, lod);
+                direct_light += get_moon_color(world_position, layer) *
+                    get_multiple_scattering(ray_dir, config.moon_dir.xyz, moon_depth);
+            }
 
             // Frostbite energy-conversing integration
-            let S = clouds_density_sampled * (
-                ambient_light.rgb +
-                config.sun_color.rgb * scattering * volumetric_shadow(world_position, ray_dot_sun)
-            );
+            let S = clouds_density_sampled * (ambient_light + direct_light);
             let delta_transmittance = exp(-clouds_density_sampled * ray.step_distance);
             let integrated_scattering = S * (1.0 - delta_transmittance) / clouds_density_sampled;
 
@@ -227,8 +457,165 @@ fn raymarch(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32) -> RaymarchResult
     return RaymarchResult(dist, vec4f(scattered_light, transmittance));
 }
 
-// TODO: replace this by reading from Bevy's internal atmosphere rendering LUTs
-// https://github.com/bevyengine/bevy/blob/v0.17.0/crates/bevy_pbr/src/atmosphere/functions.wgsl
+// Bilinearly sample the cloud atlas, which repeats, at `uv` in units of the size of the atlas.
+fn sample_atlas(uv: vec2f) -> vec4f {
+    let size = vec2i(textureDimensions(clouds_atlas_texture));
+    let coord = uv * vec2f(size) - 0.5;
+    let base = vec2i(floor(coord));
+    let f = coord - floor(coord);
+
+    var color = vec4f(0.0);
+    for (var i = 0u; i < 4u; i++) {
+        let corner = vec2i(i32(i & 1u), i32(i >> 1u));
+        let texel = ((base + corner) % size + size) % size;
+        let bilinear = mix(1.0 - f, f, vec2f(corner));
+        color += textureLoad(clouds_atlas_texture, texel, 0) * bilinear.x * bilinear.y;
+    }
+    return color;
+}
+
+// How much the texture of a flat layer is stretched along the X axis, which turns its clouds into
+// streaks.
+const FLAT_LAYER_STRETCH = 4.0;
+
+// Density of the clouds of a flat layer at a point of its sheet, from two octaves of the cloud atlas.
+fn get_flat_layer_density(position: vec3f, layer: CloudLayer) -> f32 {
+    let uv = position.xz * (0.00005 * layer.base_scale) / vec2f(FLAT_LAYER_STRETCH, 1.0);
+    let base = sample_atlas(uv).r;
+    let detail = sample_atlas(uv * layer.detail_scale).r;
+    let m = base - (1.0 - detail) * config.clouds_detail_strength;
+    return saturate(common::remap(m, 1.0 - layer.coverage, 1.0)) * layer.density;
+}
+
+// Render a flat layer as a thin sheet at its `bottom_height`, with a single sample of its density.
+// The path of the ray through the sheet is as long as if the layer was `top_height` thick.
+fn flat_layer(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32, layer: CloudLayer) -> RaymarchResult {
+    let radius = config.planet_radius + layer.bottom_height;
+    let sheet = intersect_sphere(ray_origin, ray_dir, radius);
+
+    // The nearest intersection in front of the camera, if the camera is below the sheet that is the
+    // far one.
+    var dist = sheet.x;
+    if dist < 0.0 {
+        dist = sheet.y;
+    }
+    if dist < 0.0 || dist >= max_dist {
+        return RaymarchResult(max_dist, vec4f(0.0, 0.0, 0.0, 1.0));
+    }
+
+    let position = ray_origin + ray_dir * dist;
+    let density = get_flat_layer_density(position, layer);
+    if density <= 0.0 {
+        return RaymarchResult(max_dist, vec4f(0.0, 0.0, 0.0, 1.0));
+    }
+
+    // The path through the sheet gets longer towards the horizon, limited to keep it finite.
+    let cos_angle = abs(dot(normalize(position), ray_dir));
+    let thickness = layer.top_height - layer.bottom_height;
+    let transmittance = exp(-density * thickness / max(cos_angle, 0.05));
+
+    // A thin sheet is lit by the sky from above and barely shadows itself.
+    var light = get_ambient_top() +
+        get_sun_color(position, layer) * get_scattering(ray_dir, config.sun_dir.xyz);
+    if is_moon_lighting() {
+        light += get_moon_color(position, layer) * get_scattering(ray_dir, config.moon_dir.xyz);
+    }
+    return RaymarchResult(dist, vec4f(light * (1.0 - transmittance), transmittance));
+}
+
+// Render all layers of clouds along a ray from `ray_origin`, relative to the center of the planet,
+// and composite them from front to back.
+fn render_layers(ray_origin: vec3f, ray_dir: vec3f, max_dist: f32) -> RaymarchResult {
+    let layer_count = get_layer_count();
+    var results: array<RaymarchResult, MAX_CLOUD_LAYERS + 1u>;
+    for (var i = 0u; i < layer_count; i++) {
+        let layer = get_layer(i);
+        // The clouds of each layer move with their own wind.
+        let origin = ray_origin - layer.wind_displacement;
+        if layer.mode == CLOUD_LAYER_FLAT {
+            results[i] = flat_layer(origin, ray_dir, max_dist, layer);
+        } else {
+            results[i] = raymarch(origin, ray_dir, max_dist, layer);
+        }
+    }
+
+    // The layers don't overlap, so the clouds that are hit first lie in front of the others.
+    var composited = RaymarchResult(max_dist, vec4f(0.0, 0.0, 0.0, 1.0));
+    var done = 0u;
+    for (var n = 0u; n < layer_count; n++) {
+        var nearest = layer_count;
+        for (var i = 0u; i < layer_count; i++) {
+            if (done & (1u << i)) == 0u && (nearest == layer_count || results[i].dist < results[nearest].dist) {
+                nearest = i;
+            }
+        }
+        done |= 1u << nearest;
+
+        let result = results[nearest];
+        composited.dist = min(composited.dist, result.dist);
+        composited.color = vec4f(
+            composited.color.rgb + composited.color.a * result.color.rgb,
+            composited.color.a * result.color.a
+        );
+    }
+    return composited;
+}
+
+// Color of the sky at night, when the sun is far below the horizon.
+const NIGHT_SKY_COLOR = vec3f(0.002, 0.004, 0.01);
+
+// Number of cells of the grid of stars along each axis of the unit cube around the camera.
+const STARS_GRID_SIZE = 200.0;
+// The fraction of the cells of the grid that contain a star.
+const STARS_DENSITY = 0.04;
+
+// Angular radius of the moon in radians, about three times its real size.
+const MOON_ANGULAR_RADIUS = 0.015;
+// Radiance of the lit part of the moon relative to `moon_color`.
+const MOON_BRIGHTNESS = 40.0;
+
+// A procedural star field: at most one star in each cell of a grid of directions, with a random
+// position and brightness.
+fn get_stars(ray_dir: vec3f) -> vec3f {
+    let p = ray_dir * STARS_GRID_SIZE;
+    let cell = floor(p);
+    let star = common::hash13(cell);
+    if star > STARS_DENSITY {
+        return vec3f(0.0);
+    }
+
+    // Distance between the ray and the star, in cells.
+    let center = cell + 0.5 + 0.4 * common::hash33(cell);
+    let offset = length(center - ray_dir * dot(center, ray_dir));
+    let brightness = pow(1.0 - star / STARS_DENSITY, 4.0) * smoothstep(0.3, 0.0, offset);
+
+    // Stars range from orange to blue and are dimmed by the air near the horizon.
+    let temperature = common::hash13(cell + 17.0);
+    let color = mix(vec3f(1.0, 0.75, 0.5), vec3f(0.7, 0.8, 1.0), temperature);
+    return color * brightness * smoothstep(0.0, 0.2, ray_dir.y);
+}
+
+// Coverage of the disk of the moon along `ray_dir` and how much of it is lit by the sun there.
+fn get_moon_disk(ray_dir: vec3f) -> vec2f {
+    let moon_dir = config.moon_dir.xyz;
+    let cos_angle = dot(ray_dir, moon_dir);
+    if cos_angle <= 0.0 {
+        return vec2f(0.0);
+    }
+
+    // The point on the disk in units of its radius, and the normal of the sphere of the moon there,
+    // which faces the camera.
+    let disk = (ray_dir / cos_angle - moon_dir) / tan(MOON_ANGULAR_RADIUS);
+    let r = length(disk);
+    let normal = disk - moon_dir * sqrt(max(1.0 - r * r, 0.0));
+
+    let coverage = smoothstep(1.0, 0.9, r) * step(0.0, ray_dir.y);
+    let lit = smoothstep(-0.05, 0.05, dot(normal, config.sun_dir.xyz));
+    return vec2f(coverage, lit);
+}
+
+// A hand-tuned sky, for views without Bevy's `Atmosphere`. It fades to a dark sky with stars and
+// the moon at night.
 fn get_sky_color(ray_dir: vec3f) -> vec3f {
     let mu = clamp(dot(ray_dir, config.sun_dir.xyz), 0.0, 1.0);
     let ray_dir_y = max(ray_dir.y, 0.01);
@@ -245,6 +632,16 @@ fn get_sky_color(ray_dir: vec3f) -> vec3f {
     // Horizon
     col += horizon_strength * clamp((1.0 - ray_dir.y * 10.0), 0.0, 1.0);
 
+    // Night, the sky darkens during twilight
+    let daylight = smoothstep(-0.2, 0.1, config.sun_dir.y);
+    col = mix(NIGHT_SKY_COLOR, col, daylight);
+
+    // Stars and the moon, which hides the stars behind it
+    let moon = get_moon_disk(ray_dir);
+    let night = (1.0 - daylight) * (1.0 - daylight);
+    col += config.stars_intensity * night * (1.0 - moon.x) * get_stars(ray_dir);
+    col += MOON_BRIGHTNESS * moon.x * moon.y * config.moon_color.rgb;
+
     // Sun
     col += 0.25 * config.sun_color.rgb * pow(mu, 6.0);
     col += 0.25 * config.sun_color.rgb * pow(mu, 64.0);
@@ -253,44 +650,21 @@ fn get_sky_color(ray_dir: vec3f) -> vec3f {
     return col;
 }
 
-fn render_clouds_atlas(frag_coord: vec2f) -> vec4f {
-    let v_uv = frag_coord / config.render_resolution.xy;
-    let coord = vec3f(v_uv, 0.5);
-
-    let mfbm = 0.9;
-    let mvor = 0.7;
-
-    return vec4f(
-        mix(1.0, common::tilable_perlin_fbm(coord, 7, 4), mfbm) *
-            mix(1.0, common::tilable_voronoi(coord, 8, 9.0), mvor),
-        0.625 * common::tilable_voronoi(coord, 3, 15.0) +
-            0.250 * common::tilable_voronoi(coord, 3, 19.0) +
-            0.125 * common::tilable_voronoi(coord, 3, 23.0) -
-            1.0,
-        1.0 - common::tilable_voronoi(coord + 0.5, 6, 9.0),
-        1.0
-    );
-}
-
-fn render_clouds_worley(coord: vec3f) -> vec4f {
-    let r = common::tilable_voronoi(coord, 16, 3.0);
-    let g = common::tilable_voronoi(coord, 4, 8.0);
-    let b = common::tilable_voronoi(coord, 4, 16.0);
-
-    let c = max(0.0, 1.0 - (r + g * 0.5 + b * 0.25) / 1.75);
-
-    return vec4f(c);
-}
-
-fn get_clouds_color(frag_coord: vec2f, camera: mat4x4f, old_cam: mat4x4f, ray_dir: vec3f, ray_origin: vec3f) -> vec4f {
-    if (frag_coord.y < 1.5) {
-        if frag_coord.x < 1.0 { return vec4f(config.render_resolution.xy, 0.0, 0.0); }
-        return common::save_camera(camera, frag_coord, ray_origin);
-    }
-
-    let result = raymarch(ray_origin, ray_dir, MAX_DISTANCE);
+// Returns the clouds in front of `max_dist` and the distance to the clouds, which is used for
+// reprojection.
+fn get_clouds_color(ray_dir: vec3f, ray_origin: vec3f, max_dist: f32) -> RaymarchResult {
+    let result = render_layers(ray_origin, ray_dir, max_dist);
     let transmittance = result.color.a;
 
+#ifdef ATMOSPHERE
+    // The air in front of the clouds attenuates them and scatters light towards the camera where
+    // the clouds block the sky behind them, see `aerial_perspective`.
+    let air = atmosphere::aerial_perspective(config.camera_translation, ray_dir, result.dist);
+    let col = vec4f(
+        result.color.rgb * air.transmittance + air.inscattering * (1.0 - transmittance),
+        transmittance
+    );
+#else
     let fog_falloff = 1.0e-4;
     let fog_factor = 0.8 - exp(-fog_falloff * result.dist);
 
@@ -302,32 +676,186 @@ fn get_clouds_color(frag_coord: vec2f, camera: mat4x4f, old_cam: mat4x4f, ray_di
         ),
         transmittance
     );
+#endif
 
-    // For now, just don't mix two frames when camera transform changed too much.
-    // TODO: properly reproject old frame's reprojected pixel onto current frame.
-    if length(
-        abs(old_cam[0] - camera[0]) +
-        abs(old_cam[1] - camera[1]) +
-        abs(old_cam[2] - camera[2]) +
-        abs(old_cam[3] - camera[3])
-    ) > EPSILON {
-        return col;
-    }
-
-    let original_color = textureLoad(
-        clouds_render_texture,
-        vec2u(u32(frag_coord.x),
-        u32(config.render_resolution.y - 1.0) - u32(frag_coord.y))
-    );
-    return mix(col, original_color, config.reprojection_strength);
+    return RaymarchResult(result.dist, col);
 }
 
-fn get_ray_origin(time: f32) -> vec3f {
-    return (
-        config.camera_translation -
-        config.wind_displacement +
-        vec3f(0.0, config.planet_radius, 0.0)
-    );
+struct Reprojection {
+    // Coordinates of the pixel in the previous frame, in pixels.
+    coord: vec2f,
+    // The distance to the clouds we expect to find in the history.
+    dist: f32,
+    valid: bool,
+}
+
+// The layer whose clouds are the nearest to a point relative to the center of the planet.
+fn get_nearest_layer(position: vec3f) -> CloudLayer {
+    let height = length(position) - config.planet_radius;
+    var nearest = main_layer();
+    var nearest_distance = MAX_DISTANCE;
+    for (var i = 0u; i < get_layer_count(); i++) {
+        let layer = get_layer(i);
+        let distance = max(max(layer.bottom_height - height, height - layer.top_height), 0.0);
+        if distance < nearest_distance {
+            nearest = layer;
+            nearest_distance = distance;
+        }
+    }
+    return nearest;
+}
+
+// Find where the clouds at `dist` along `ray_dir` were visible in the previous frame.
+fn reproject(ray_dir: vec3f, dist: f32) -> Reprojection {
+    var previous_clip: vec4f;
+    var previous_dist = MAX_DISTANCE;
+
+    if dist >= MAX_DISTANCE {
+        // Nothing was hit, so only the rotation of the camera matters.
+        previous_clip = config.previous_clip_from_world * vec4f(ray_dir, 0.0);
+    } else {
+        // The clouds moved along with the wind of their layer since the previous frame.
+        let layer = get_nearest_layer(get_ray_origin() + ray_dir * dist);
+        let wind_delta = layer.wind_displacement - layer.previous_wind_displacement;
+        let previous_position = config.camera_translation + ray_dir * dist - wind_delta;
+        previous_clip = config.previous_clip_from_world * vec4f(previous_position, 1.0);
+        previous_dist = length(previous_position - config.previous_camera_translation);
+    }
+
+    if previous_clip.w <= 0.0 {
+        return Reprojection(vec2f(0.0), previous_dist, false);
+    }
+
+    let uv = previous_clip.xy / previous_clip.w * vec2f(0.5, -0.5) + 0.5;
+    let valid = all(uv >= vec2f(0.0)) && all(uv <= vec2f(1.0));
+    return Reprojection(uv * config.render_resolution, previous_dist, valid);
+}
+
+// Bilinearly sample the history at the given pixel coordinates.
+fn sample_history(coord: vec2f) -> vec4f {
+    let max_pixel = vec2i(config.render_resolution) - 1;
+    let base = floor(coord - 0.5);
+    let f = coord - 0.5 - base;
+
+    var color = vec4f(0.0);
+    for (var i = 0u; i < 4u; i++) {
+        let corner = vec2f(f32(i & 1u), f32(i >> 1u));
+        let texel = clamp(vec2i(base + corner), vec2i(0), max_pixel);
+        let bilinear = mix(1.0 - f, f, corner);
+        color += textureLoad(clouds_history_texture, texel) * bilinear.x * bilinear.y;
+    }
+    return color;
+}
+
+// Relative difference in distance to the clouds above which the history is considered to show
+// something else than the current frame (disocclusion).
+const DISOCCLUSION_THRESHOLD = 0.25;
+
+fn is_disoccluded(reprojection: Reprojection) -> bool {
+    let texel = min(vec2i(reprojection.coord), vec2i(config.render_resolution) - 1);
+    let history_dist = textureLoad(clouds_history_depth_texture, texel).r;
+    if history_dist >= MAX_DISTANCE && reprojection.dist >= MAX_DISTANCE {
+        return false;
+    }
+    let difference = abs(history_dist - reprojection.dist) / max(history_dist, reprojection.dist);
+    return difference > DISOCCLUSION_THRESHOLD;
+}
+
+// Offsets within a 4x4 tile in the order in which they are raymarched, following a Bayer matrix.
+// The first four offsets divided by two form the order of a 2x2 Bayer matrix.
+const BAYER_OFFSETS = array<vec2u, 16>(
+    vec2u(0, 0), vec2u(2, 2), vec2u(2, 0), vec2u(0, 2),
+    vec2u(1, 1), vec2u(3, 3), vec2u(3, 1), vec2u(1, 3),
+    vec2u(1, 0), vec2u(3, 2), vec2u(3, 0), vec2u(1, 2),
+    vec2u(0, 1), vec2u(2, 3), vec2u(2, 1), vec2u(0, 3),
+);
+
+fn is_checkerboard() -> bool {
+    return all(config.interleave_tile_size == vec2u(2, 1));
+}
+
+// Offset of the pixel within each tile that gets raymarched this frame (ignoring checkerboard).
+fn get_tile_offset() -> vec2u {
+    let tile_size = config.interleave_tile_size;
+    return BAYER_OFFSETS[config.frame_index % (tile_size.x * tile_size.y)] / (4u / tile_size);
+}
+
+// The pixel that gets raymarched this frame by an invocation of `update`.
+fn get_traced_pixel(invocation: vec2u) -> vec2u {
+    if is_checkerboard() {
+        return vec2u(invocation.x * 2u + ((invocation.y + config.frame_index) & 1u), invocation.y);
+    }
+    return invocation * config.interleave_tile_size + get_tile_offset();
+}
+
+fn is_traced(pixel: vec2u) -> bool {
+    if is_checkerboard() {
+        return ((pixel.x + pixel.y + config.frame_index) & 1u) == 0u;
+    }
+    return all(pixel % config.interleave_tile_size == get_tile_offset());
+}
+
+// Interpolate the clouds of a pixel that was not raymarched this frame from the surrounding pixels
+// that were raymarched this frame.
+fn interpolate_traced_pixels(pixel: vec2u) -> RaymarchResult {
+    let max_pixel = vec2i(config.render_resolution) - 1;
+    var color = vec4f(0.0);
+    var total_weight = 0.0;
+    var dist = MAX_DISTANCE;
+    var max_weight = 0.0;
+
+    if is_checkerboard() {
+        // The direct neighbours of a pixel are always raymarched.
+        let neighbours = array<vec2i, 4>(vec2i(-1, 0), vec2i(1, 0), vec2i(0, -1), vec2i(0, 1));
+        for (var i = 0; i < 4; i++) {
+            let neighbour = vec2i(pixel) + neighbours[i];
+            if any(neighbour < vec2i(0)) || any(neighbour > max_pixel) { continue; }
+
+            color += textureLoad(clouds_raymarch_texture, neighbour);
+            total_weight += 1.0;
+            if max_weight == 0.0 {
+                max_weight = 1.0;
+                dist = textureLoad(clouds_depth_texture, neighbour).r;
+            }
+        }
+        return RaymarchResult(dist, color / max(total_weight, EPSILON));
+    }
+
+    // Bilinear interpolation between the raymarched pixels of the four surrounding tiles.
+    let tile_size = vec2i(config.interleave_tile_size);
+    let offset = vec2i(get_tile_offset());
+    let max_tile = max((max_pixel - offset) / tile_size, vec2i(0));
+    let tile_coord = vec2f(vec2i(pixel) - offset) / vec2f(tile_size);
+    let base = vec2i(floor(tile_coord));
+    let f = tile_coord - vec2f(base);
+
+    for (var i = 0u; i < 4u; i++) {
+        let corner = vec2u(i & 1u, i >> 1u);
+        let tile = clamp(base + vec2i(corner), vec2i(0), max_tile);
+        let bilinear = mix(1.0 - f, f, vec2f(corner));
+        let weight = bilinear.x * bilinear.y;
+        let traced_pixel = tile * tile_size + offset;
+
+        color += textureLoad(clouds_raymarch_texture, traced_pixel) * weight;
+        total_weight += weight;
+        if weight > max_weight {
+            max_weight = weight;
+            dist = textureLoad(clouds_depth_texture, traced_pixel).r;
+        }
+    }
+    return RaymarchResult(dist, color / max(total_weight, EPSILON));
+}
+
+// Distance to the opaque geometry behind a pixel of the clouds, sampled from the depth prepass.
+fn get_scene_distance(pixel: vec2u) -> f32 {
+    let uv = (vec2f(pixel) + 0.5) / config.render_resolution;
+    let depth_pixel = vec2u(config.viewport.xy + uv * config.viewport.zw);
+    return scene_distance(uv, textureLoad(depth_texture, depth_pixel, 0));
+}
+
+// The position of the camera relative to the center of the planet.
+fn get_ray_origin() -> vec3f {
+    return config.camera_translation + vec3f(0.0, config.planet_radius, 0.0);
 }
 
 fn get_ray_direction(frag_coord: vec2f) -> vec3f {
@@ -345,47 +873,154 @@ fn get_ray_direction(frag_coord: vec2f) -> vec3f {
     return normalize(ray_world.xyz);
 }
 
+// Raymarch the pixels that should be updated this frame, see `Interleaving`.
+@compute @workgroup_size(8, 8, 1)
+fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pixel = get_traced_pixel(invocation_id.xy);
+
+    // The dispatch size is rounded up to a multiple of the workgroup size.
+    if any(pixel >= vec2u(config.render_resolution)) {
+        return;
+    }
+
+    let ray_origin = get_ray_origin();
+    let ray_dir = get_ray_direction(vec2f(pixel) + vec2f(0.5));
+    let clouds = get_clouds_color(ray_dir, ray_origin, get_scene_distance(pixel));
+
+    textureStore(clouds_raymarch_texture, pixel, clouds.color);
+    textureStore(clouds_depth_texture, pixel, vec4f(clouds.dist));
+}
+
+// Render the sky and interpolate the pixels that were not raymarched this frame.
 @compute @workgroup_size(8, 8, 1)
-fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let index = vec2f(f32(invocation_id.x), f32(invocation_id.y)) + vec2f(0.5);
-    let inverted_y_coord = config.render_resolution.y - index.y;
+fn interpolate(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pixel = invocation_id.xy;
 
-    let worley_coord = vec2f(index.x, inverted_y_coord);
+    if any(pixel >= vec2u(config.render_resolution)) {
+        return;
+    }
 
-    let z = floor(worley_coord.x / WORLEY_RESOLUTION_F32) + 8.0 * floor(worley_coord.y / WORLEY_RESOLUTION_F32);
-    let xy = vec2f(index.x, inverted_y_coord) % WORLEY_RESOLUTION_F32;
-    let xyz = vec3f(xy, z);
+    let ray_dir = get_ray_direction(vec2f(pixel) + vec2f(0.5));
+    textureStore(sky_texture, pixel, vec4f(get_sky_color(ray_dir), 1.0));
 
-    let worley_col = render_clouds_worley(xyz / WORLEY_RESOLUTION_F32);
-    let atlas_col = render_clouds_atlas(vec2f(index.x, inverted_y_coord));
+    if is_traced(pixel) {
+        return;
+    }
 
-    storageBarrier();
+    let clouds = interpolate_traced_pixels(pixel);
+    textureStore(clouds_raymarch_texture, pixel, clouds.color);
+    textureStore(clouds_depth_texture, pixel, vec4f(clouds.dist));
+}
 
-    textureStore(clouds_atlas_texture, invocation_id.xy, atlas_col);
-    textureStore(clouds_worley_texture, vec3u(u32(xyz.x), u32(xyz.y), u32(xyz.z)), worley_col);
+// The weight of the history of a pixel that was raymarched this frame. Each pixel is only raymarched
+// once per tile of pixels, so its fresh sample gets a correspondingly larger weight, such that the
+// history converges as fast as with `reprojection_strength` when every pixel gets raymarched.
+fn get_traced_history_weight() -> f32 {
+    let tile_count = f32(config.interleave_tile_size.x * config.interleave_tile_size.y);
+    return saturate(1.0 - tile_count * (1.0 - config.reprojection_strength));
 }
 
+// Blend the clouds of this frame with the reprojected clouds of previous frames to reduce noise.
 @compute @workgroup_size(8, 8, 1)
-fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>, @builtin(num_workgroups) num_workgroups: vec3<u32>) {
-    let index = vec2f(f32(invocation_id.x), f32(invocation_id.y)) + vec2f(0.5);
-
-    // Load old camera matrix before storageBarrier to prevent race conditions;
-    let sample_y = u32(config.render_resolution.y) - 1;
-    let old_cam = mat4x4f(
-        textureLoad(clouds_render_texture, vec2u(1, sample_y)),
-        textureLoad(clouds_render_texture, vec2u(2, sample_y)),
-        textureLoad(clouds_render_texture, vec2u(3, sample_y)),
-        textureLoad(clouds_render_texture, vec2u(4, sample_y)),
-    );
-    var frag_coord = vec2f(index.x, config.render_resolution.y - index.y);
+fn resolve(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pixel = invocation_id.xy;
+
+    if any(pixel >= vec2u(config.render_resolution)) {
+        return;
+    }
 
-    var ray_origin = get_ray_origin(config.time);
-    var ray_dir = get_ray_direction(index);
-    var col = get_clouds_color(frag_coord, config.inverse_camera_view, old_cam, ray_dir, ray_origin);
-    let sky_color = vec4f(get_sky_color(ray_dir), 1.0);
+    let current = textureLoad(clouds_raymarch_texture, pixel);
+    let ray_dir = get_ray_direction(vec2f(pixel) + vec2f(0.5));
+    let reprojection = reproject(ray_dir, textureLoad(clouds_depth_texture, pixel).r);
 
-    storageBarrier();
+    if config.history_valid == 0u || !reprojection.valid || is_disoccluded(reprojection) {
+        textureStore(clouds_render_texture, pixel, current);
+        return;
+    }
+
+    // Clamp the history to the colors in the neighbourhood of this frame to prevent ghosting.
+    let max_pixel = vec2i(config.render_resolution) - 1;
+    var neighbourhood_min = current;
+    var neighbourhood_max = current;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour = clamp(vec2i(pixel) + vec2i(x, y), vec2i(0), max_pixel);
+            let color = textureLoad(clouds_raymarch_texture, neighbour);
+            neighbourhood_min = min(neighbourhood_min, color);
+            neighbourhood_max = max(neighbourhood_max, color);
+        }
+    }
+    let history = clamp(sample_history(reprojection.coord), neighbourhood_min, neighbourhood_max);
+
+    // Pixels that were not raymarched this frame only have an interpolated color, so they rely on
+    // the history entirely.
+    var history_weight = 1.0;
+    if is_traced(pixel) {
+        history_weight = get_traced_history_weight();
+    }
+    textureStore(clouds_render_texture, pixel, mix(current, history, history_weight));
+}
+
+// Render the sky and the clouds into a face of the cubemap of an environment map, see
+// `CloudsEnvironmentMap`. The face is set up like a view with a field of view of 90°.
+@compute @workgroup_size(8, 8, 1)
+fn environment_map(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let pixel = invocation_id.xy;
+
+    if any(pixel >= vec2u(config.render_resolution)) {
+        return;
+    }
+
+    let ray_dir = get_ray_direction(vec2f(pixel) + vec2f(0.5));
+    let clouds = get_clouds_color(ray_dir, get_ray_origin(), MAX_DISTANCE).color;
+    let sky = get_sky_color(ray_dir);
+    textureStore(environment_map_texture, pixel, vec4f(clouds.rgb + sky * clouds.a, 1.0));
+}
+
+// Number of samples of the density of the clouds along each ray of the shadow map.
+const SHADOW_MAP_STEPS = 32u;
+
+// Render the transmittance of the clouds between the ground and the sun into the shadow map, see
+// `CloudsShadowMap`. Each texel covers a point on the ground plane of the scene.
+@compute @workgroup_size(8, 8, 1)
+fn shadow_map(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(shadow_map_texture);
+    let pixel = invocation_id.xy;
+
+    if any(pixel >= size) {
+        return;
+    }
+
+    let uv = (vec2f(pixel) + 0.5) / vec2f(size);
+    let ground = config.shadow_map_center.xz + (uv - 0.5) * config.shadow_map_extent;
+    let sun_dir = config.sun_dir.xyz;
+
+    var optical_depth = 0.0;
+    for (var i = 0u; i < get_layer_count(); i++) {
+        let layer = get_layer(i);
+        let origin = vec3f(ground.x, 0.0, ground.y) - layer.wind_displacement +
+            vec3f(0.0, config.planet_radius, 0.0);
+
+        // The ray starts below the cloud layer, so it leaves through the far side of both spheres.
+        let start = intersect_sphere(origin, sun_dir, config.planet_radius + layer.bottom_height).y;
+        let end = intersect_sphere(origin, sun_dir, config.planet_radius + layer.top_height).y;
+
+        if layer.mode == CLOUD_LAYER_FLAT {
+            optical_depth += get_flat_layer_density(origin + sun_dir * start, layer) * (end - start);
+            continue;
+        }
+
+        let step_distance = max(end - start, 0.0) / f32(SHADOW_MAP_STEPS);
+        let lod = get_detail_lod(config.shadow_map_extent / f32(size.x), layer);
+        for (var step = 0u; step < SHADOW_MAP_STEPS; step++) {
+            let position = origin + sun_dir * (start + (f32(step) + 0.5) * step_distance);
+            let normalized_height = clamp(get_normalized_height(position, layer), 0.0, 1.0);
+            optical_depth +=
+                get_cloud_map_density(position, normalized_height, layer, lod) * step_distance;
+        }
+    }
 
-    textureStore(clouds_render_texture, invocation_id.xy, col);
-    textureStore(sky_texture, invocation_id.xy, sky_color);
+    // The shadows fade out as the sun sets, when its rays pass through a lot of clouds.
+    let transmittance = mix(1.0, exp(-optical_depth), smoothstep(0.0, 0.05, sun_dir.y));
+    textureStore(shadow_map_texture, pixel, vec4f(vec3f(transmittance), 1.0));
 }
@@ -0,0 +1,106 @@
+#import bevy_open_world::common
+
+@group(0) @binding(0) var clouds_atlas_texture: texture_storage_2d<rgba8unorm, write>;
+// A mip level of the Worley noise, the first by `init_worley` and the others by `downsample_worley`.
+// Only the red channel is used, see `CloudsTextureFormats`.
+#ifdef WORLEY_FORMAT_RGBA8UNORM
+@group(0) @binding(1) var clouds_worley_texture: texture_storage_3d<rgba8unorm, write>;
+#else
+@group(0) @binding(1) var clouds_worley_texture: texture_storage_3d<r8unorm, write>;
+#endif
+// The previous mip level of the Worley noise, only used by `downsample_worley`.
+@group(0) @binding(2) var clouds_worley_source_texture: texture_3d<f32>;
+@group(0) @binding(3) var clouds_curl_texture: texture_storage_2d<rgba8unorm, write>;
+
+fn render_clouds_atlas(frag_coord: vec2f) -> vec4f {
+    let v_uv = frag_coord / vec2f(textureDimensions(clouds_atlas_texture));
+    let coord = vec3f(v_uv, 0.5);
+
+    let mfbm = 0.9;
+    let mvor = 0.7;
+
+    return vec4f(
+        mix(1.0, common::tilable_perlin_fbm(coord, 7, 4), mfbm) *
+            mix(1.0, common::tilable_voronoi(coord, 8, 9.0), mvor),
+        // Offset by 1 to fit the format, the clouds subtract it again.
+        0.625 * common::tilable_voronoi(coord, 3, 15.0) +
+            0.250 * common::tilable_voronoi(coord, 3, 19.0) +
+            0.125 * common::tilable_voronoi(coord, 3, 23.0),
+        1.0 - common::tilable_voronoi(coord + 0.5, 6, 9.0),
+        1.0
+    );
+}
+
+fn render_clouds_worley(coord: vec3f) -> vec4f {
+    let r = common::tilable_voronoi(coord, 16, 3.0);
+    let g = common::tilable_voronoi(coord, 4, 8.0);
+    let b = common::tilable_voronoi(coord, 4, 16.0);
+
+    let c = max(0.0, 1.0 - (r + g * 0.5 + b * 0.25) / 1.75);
+
+    return vec4f(c);
+}
+
+fn curl_potential(uv: vec2f) -> f32 {
+    return common::tilable_perlin_fbm(vec3f(fract(uv), 0.5), 4, 4.0);
+}
+
+// The curl of tiling 2D noise, a divergence free flow of horizontal directions which distorts the
+// details of the clouds. The directions are mapped from -1..1 to 0..1 to fit the format.
+fn render_curl_noise(uv: vec2f) -> vec4f {
+    let epsilon = 1.0 / f32(textureDimensions(clouds_curl_texture).x);
+    let dx = vec2f(epsilon, 0.0);
+    let dy = vec2f(0.0, epsilon);
+
+    let dn_dx = curl_potential(uv + dx) - curl_potential(uv - dx);
+    let dn_dy = curl_potential(uv + dy) - curl_potential(uv - dy);
+    let curl = vec2f(dn_dy, -dn_dx);
+
+    let direction = curl / max(length(curl), 0.000001);
+    return vec4f(direction * 0.5 + 0.5, 0.5, 1.0);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(clouds_atlas_texture);
+    if any(invocation_id.xy >= size) {
+        return;
+    }
+
+    let index = vec2f(f32(invocation_id.x), f32(invocation_id.y)) + vec2f(0.5);
+    let inverted_y_coord = f32(size.y) - index.y;
+    let atlas_col = render_clouds_atlas(vec2f(index.x, inverted_y_coord));
+    textureStore(clouds_atlas_texture, invocation_id.xy, atlas_col);
+
+    let curl_size = textureDimensions(clouds_curl_texture);
+    if all(invocation_id.xy < curl_size) {
+        let uv = (vec2f(invocation_id.xy) + 0.5) / vec2f(curl_size);
+        textureStore(clouds_curl_texture, invocation_id.xy, render_curl_noise(uv));
+    }
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn init_worley(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let size = textureDimensions(clouds_worley_texture);
+    if any(invocation_id >= size) {
+        return;
+    }
+
+    let coord = (vec3f(invocation_id) + 0.5) / vec3f(size);
+    textureStore(clouds_worley_texture, invocation_id, render_clouds_worley(coord));
+}
+
+// Average 2x2x2 texels of the previous mip level of the Worley noise into a texel of the next.
+@compute @workgroup_size(4, 4, 4)
+fn downsample_worley(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if any(invocation_id >= textureDimensions(clouds_worley_texture)) {
+        return;
+    }
+
+    var color = vec4f(0.0);
+    for (var i = 0u; i < 8u; i++) {
+        let offset = vec3u(i & 1u, (i >> 1u) & 1u, i >> 2u);
+        color += textureLoad(clouds_worley_source_texture, invocation_id * 2u + offset, 0);
+    }
+    textureStore(clouds_worley_texture, invocation_id, color / 8.0);
+}
@@ -0,0 +1,42 @@
+#import bevy_open_world::shadows::{CloudsShadows, clouds_shadow}
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#endif
+
+// A `StandardMaterial` which is shadowed by the clouds, see `CloudsShadows`.
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(100) var<uniform> shadows: CloudsShadows;
+@group(#{MATERIAL_BIND_GROUP}) @binding(101) var shadow_map: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(102) var shadow_sampler: sampler;
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+#ifdef PREPASS_PIPELINE
+    // The deferred lighting pass doesn't know about the clouds.
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    let shadow = clouds_shadow(shadows, shadow_map, shadow_sampler, in.world_position.xyz);
+    out.color = vec4f(out.color.rgb * shadow, out.color.a);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+
+    return out;
+}
@@ -0,0 +1,102 @@
+#define_import_path bevy_open_world::config
+
+const MAX_DISTANCE: f32 = 1.0e9;
+
+// Maximum number of `CloudsLayers`, matching `MAX_CLOUD_LAYERS`.
+const MAX_CLOUD_LAYERS = 4u;
+
+// How a `CloudLayer` gets rendered, see `CloudLayerMode`.
+const CLOUD_LAYER_VOLUMETRIC = 0u;
+const CLOUD_LAYER_FLAT = 1u;
+
+struct CloudLayer {
+    // How far the clouds of the layer moved with the wind.
+    wind_displacement: vec3f,
+    previous_wind_displacement: vec3f,
+    bottom_height: f32,
+    top_height: f32,
+    coverage: f32,
+    density: f32,
+    base_scale: f32,
+    detail_scale: f32,
+    cloud_type: f32,
+    mode: u32,
+    use_weather_map: u32,
+}
+
+struct Config {
+    clouds_base_scale: f32,
+    clouds_raymarch_steps_count: u32,
+    clouds_bottom_height: f32,
+    clouds_top_height: f32,
+    clouds_coverage: f32,
+    cloud_type: f32,
+    clouds_density: f32,
+    clouds_detail_scale: f32,
+    clouds_detail_strength: f32,
+    clouds_curl_strength: f32,
+    clouds_curl_scale: f32,
+    clouds_base_edge_softness: f32,
+    clouds_bottom_softness: f32,
+    clouds_shadow_raymarch_steps_count: u32,
+    clouds_shadow_raymarch_step_size: f32,
+    clouds_shadow_raymarch_step_multiply: f32,
+    clouds_ambient_color_top: vec4f,
+    clouds_ambient_color_bottom: vec4f,
+    clouds_min_transmittance: f32,
+    planet_radius: f32,
+    forward_scattering_g: f32,
+    backward_scattering_g: f32,
+    scattering_lerp: f32,
+    multiple_scattering_octaves: u32,
+    multiple_scattering_attenuation: f32,
+    multiple_scattering_contribution: f32,
+    multiple_scattering_eccentricity: f32,
+    powder_strength: f32,
+    sun_dir: vec4f,
+    sun_color: vec4f,
+    moon_dir: vec4f,
+    moon_color: vec4f,
+    stars_intensity: f32,
+    crepuscular_rays_intensity: f32,
+    crepuscular_rays_decay: f32,
+    crepuscular_rays_samples: u32,
+    camera_translation: vec3f,
+    time: f32,
+    reprojection_strength: f32,
+    render_resolution: vec2f,
+    inverse_camera_view: mat4x4f,
+    inverse_camera_projection: mat4x4f,
+    wind_displacement: vec3f,
+    previous_wind_displacement: vec3f,
+    previous_camera_translation: vec3f,
+    clip_from_world: mat4x4f,
+    previous_clip_from_world: mat4x4f,
+    frame_index: u32,
+    history_valid: u32,
+    interleave_tile_size: vec2u,
+    // Origin and size of the camera's viewport in the render target, in physical pixels.
+    viewport: vec4f,
+    // The region of the scene covered by the shadow map, only used by `shadow_map`.
+    shadow_map_center: vec3f,
+    shadow_map_extent: f32,
+    // The region of the ground plane covered by the weather map, an extent of 0 if there is none.
+    weather_map_center: vec2f,
+    weather_map_extent: vec2f,
+    // The `CloudsLayers` in addition to the main layer.
+    layer_count: u32,
+    layers: array<CloudLayer, MAX_CLOUD_LAYERS>,
+};
+
+@group(0) @binding(0) var<uniform> config: Config;
+
+// Distance from the camera to the point in the scene at `uv` in the viewport with the given
+// (reverse-z) depth. Returns `MAX_DISTANCE` if there is no geometry at that point.
+fn scene_distance(uv: vec2f, depth: f32) -> f32 {
+    if depth <= 0.0 {
+        return MAX_DISTANCE;
+    }
+    let ndc = vec4f((uv * 2.0 - 1.0) * vec2f(1.0, -1.0), depth, 1.0);
+    let view_position = config.inverse_camera_projection * ndc;
+    return length(view_position.xyz / view_position.w);
+}
@@ -0,0 +1,52 @@
+#define_import_path bevy_open_world::shadows
+
+// Samples the shadow map of the clouds, see `CloudsShadowMap`. The map and the uniforms below are
+// bound by the material that uses these functions, like `CloudsShadows` does.
+
+// Where the shadow map lies in the scene.
+struct CloudsShadows {
+    // Center of the region of the scene covered by the map, in world units.
+    center: vec3f,
+    // Width and depth of the region covered by the map, 0 if there is no shadow map.
+    extent: f32,
+    // Direction towards the sun.
+    sun_dir: vec3f,
+    // How much the shadows darken the surface, between 0 and 1.
+    strength: f32,
+}
+
+// Coordinates in the shadow map of the point on the ground plane which shares its ray towards the
+// sun with `world_position`.
+fn clouds_shadow_uv(shadows: CloudsShadows, world_position: vec3f) -> vec2f {
+    let sun_dir = shadows.sun_dir;
+    let ground = world_position.xz - sun_dir.xz * (world_position.y / max(sun_dir.y, 0.05));
+    return (ground - shadows.center.xz) / shadows.extent + 0.5;
+}
+
+// Transmittance of the clouds between a point in the scene and the sun, 1 outside of the map.
+fn clouds_transmittance(
+    shadows: CloudsShadows,
+    shadow_map: texture_2d<f32>,
+    shadow_sampler: sampler,
+    world_position: vec3f,
+) -> f32 {
+    if shadows.extent <= 0.0 {
+        return 1.0;
+    }
+    let uv = clouds_shadow_uv(shadows, world_position);
+    if any(uv < vec2f(0.0)) || any(uv > vec2f(1.0)) {
+        return 1.0;
+    }
+    return textureSampleLevel(shadow_map, shadow_sampler, uv, 0.0).r;
+}
+
+// The factor by which the shadows of the clouds darken the light at a point in the scene.
+fn clouds_shadow(
+    shadows: CloudsShadows,
+    shadow_map: texture_2d<f32>,
+    shadow_sampler: sampler,
+    world_position: vec3f,
+) -> f32 {
+    let transmittance = clouds_transmittance(shadows, shadow_map, shadow_sampler, world_position);
+    return mix(1.0, transmittance, shadows.strength);
+}
@@ -0,0 +1,378 @@
+use bevy::{
+    asset::{AssetPath, embedded_path},
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialPlugin},
+    prelude::*,
+    render::{
+        Extract, ExtractSchedule, Render, RenderApp, RenderSystems,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext},
+        render_resource::{
+            AsBindGroup, BindGroup, BindGroupEntries, ComputePassDescriptor, PipelineCache,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::GpuImage,
+    },
+    shader::ShaderRef,
+};
+
+use crate::{
+    compute::{
+        CloudsLabel, CloudsNoiseBindGroup, CloudsNoiseReady, CloudsPipeline,
+        CloudsUniformBindGroup, WORKGROUP_SIZE, prepare_uniforms_bind_group,
+    },
+    config::{CloudsConfig, VolumetricClouds},
+    images::build_shadow_map_image,
+    light::update_sun_from_light,
+};
+
+/// Renders the transmittance of the clouds towards the sun into a map of a square region of the
+/// scene, which lets the clouds cast shadows onto the scene.
+///
+/// Each texel holds the transmittance of the ray from a point on the ground plane (y = 0) towards
+/// the sun, in its red, green and blue channels. The map is rendered every frame, so the shadows
+/// move along with the clouds.
+///
+/// Meshes with a [`CloudsShadowsMaterial`] are shadowed automatically. Other materials can bind
+/// the `image` and use the functions of the `bevy_open_world::shadows` shader import.
+///
+/// # Example
+///
+/// ```rust ignore
+/// fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+///     commands.insert_resource(CloudsShadowMap::new(&mut images, 512, 16_000.0));
+/// }
+/// ```
+#[derive(Resource, Clone, Debug)]
+pub struct CloudsShadowMap {
+    /// The image the transmittance is rendered to, see [`CloudsShadowMap::new`].
+    pub image: Handle<Image>,
+    /// Width and depth of the region of the scene covered by the map, in world units. Points
+    /// outside of the region are not shadowed.
+    pub extent: f32,
+    /// Center of the region of the scene covered by the map.
+    pub center: Vec3,
+    /// Whether `center` follows the active camera with [`VolumetricClouds`] with the lowest order.
+    /// It is snapped to the texels of the map, such that the shadows don't shimmer as the camera
+    /// moves.
+    pub follow_camera: bool,
+}
+
+impl CloudsShadowMap {
+    /// Creates a map of `size` by `size` texels covering `extent` by `extent` world units.
+    pub fn new(images: &mut Assets<Image>, size: u32, extent: f32) -> Self {
+        Self {
+            image: build_shadow_map_image(images, size),
+            extent,
+            center: Vec3::ZERO,
+            follow_camera: true,
+        }
+    }
+}
+
+/// A [`StandardMaterial`] that is shadowed by the clouds, see [`CloudsShadows`].
+pub type CloudsShadowsMaterial = ExtendedMaterial<StandardMaterial, CloudsShadows>;
+
+/// A [`MaterialExtension`] which darkens a [`StandardMaterial`] where the [`CloudsShadowMap`]
+/// shows clouds in front of the sun.
+///
+/// The shadow map and the direction of the sun are filled in every frame. The light of the
+/// material is darkened as a whole, including its ambient light. Only the forward renderer is
+/// supported, materials rendered with the deferred renderer are not shadowed.
+///
+/// # Example
+///
+/// ```rust ignore
+/// commands.spawn((
+///     Mesh3d(meshes.add(Plane3d::new(Vec3::Y, Vec2::splat(1e4)))),
+///     MeshMaterial3d(materials.add(CloudsShadowsMaterial {
+///         base: Color::srgb_u8(124, 144, 255).into(),
+///         extension: CloudsShadows::default(),
+///     })),
+/// ));
+/// ```
+#[derive(Asset, AsBindGroup, Reflect, Clone, Debug)]
+pub struct CloudsShadows {
+    #[uniform(100)]
+    center: Vec3,
+    #[uniform(100)]
+    extent: f32,
+    #[uniform(100)]
+    sun_dir: Vec3,
+    /// How much the shadows darken the material, between 0 and 1.
+    #[uniform(100)]
+    pub strength: f32,
+    #[texture(101)]
+    #[sampler(102)]
+    shadow_map: Option<Handle<Image>>,
+}
+
+impl Default for CloudsShadows {
+    fn default() -> Self {
+        Self {
+            center: Vec3::ZERO,
+            extent: 0.0,
+            sun_dir: Vec3::Y,
+            strength: 0.8,
+            shadow_map: None,
+        }
+    }
+}
+
+impl MaterialExtension for CloudsShadows {
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Path(
+            AssetPath::from_path_buf(embedded_path!("shaders/clouds_shadows.wgsl"))
+                .with_source("embedded"),
+        )
+    }
+}
+
+/// Move the [`CloudsShadowMap`] along with the camera.
+fn update_shadow_map_center(
+    mut shadow_map: ResMut<CloudsShadowMap>,
+    images: Res<Assets<Image>>,
+    cameras: Query<(&Camera, &GlobalTransform), With<VolumetricClouds>>,
+) {
+    if !shadow_map.follow_camera {
+        return;
+    }
+    let Some((_, transform)) = cameras
+        .iter()
+        .filter(|(camera, _)| camera.is_active)
+        .min_by_key(|(camera, _)| camera.order)
+    else {
+        return;
+    };
+    let Some(image) = images.get(&shadow_map.image) else {
+        return;
+    };
+
+    let texel_size = shadow_map.extent / image.width().max(1) as f32;
+    let center = ((transform.translation() / texel_size).round() * texel_size).with_y(0.0);
+    if shadow_map.center != center {
+        shadow_map.center = center;
+    }
+}
+
+/// Copy the [`CloudsShadowMap`] and the direction of the sun to all [`CloudsShadowsMaterial`]s.
+///
+/// This only runs when the map, the configuration or the materials changed. Only materials that are
+/// out of date are changed, as changing a material uploads it again.
+fn update_shadow_materials(
+    shadow_map: Option<Res<CloudsShadowMap>>,
+    config: Res<CloudsConfig>,
+    mut material_events: MessageReader<AssetEvent<CloudsShadowsMaterial>>,
+    mut had_shadow_map: Local<bool>,
+    mut materials: ResMut<Assets<CloudsShadowsMaterial>>,
+) {
+    // Updating the materials below modifies them as well, which runs this once more.
+    let materials_changed = !material_events.is_empty();
+    material_events.clear();
+    let shadow_map_changed = shadow_map.as_ref().is_some_and(DetectChanges::is_changed)
+        || shadow_map.is_some() != *had_shadow_map;
+    *had_shadow_map = shadow_map.is_some();
+    if !materials_changed && !shadow_map_changed && !config.is_changed() {
+        return;
+    }
+
+    let (image, center, extent) = match shadow_map.as_deref() {
+        Some(shadow_map) => (
+            Some(shadow_map.image.clone()),
+            shadow_map.center,
+            shadow_map.extent,
+        ),
+        None => (None, Vec3::ZERO, 0.0),
+    };
+    let sun_dir = config.sun_dir.truncate();
+
+    let ids: Vec<_> = materials.ids().collect();
+    for id in ids {
+        let is_up_to_date = materials.get(id).is_some_and(|material| {
+            let shadows = &material.extension;
+            shadows.shadow_map == image
+                && shadows.center == center
+                && shadows.extent == extent
+                && shadows.sun_dir == sun_dir
+        });
+        if is_up_to_date {
+            continue;
+        }
+        if let Some(material) = materials.get_mut(id) {
+            let shadows = &mut material.extension;
+            shadows.shadow_map = image.clone();
+            shadows.center = center;
+            shadows.extent = extent;
+            shadows.sun_dir = sun_dir;
+        }
+    }
+}
+
+/// A [`CloudsShadowMap`] in the render world.
+#[derive(Resource)]
+struct ExtractedShadowMap {
+    image: AssetId<Image>,
+    center: Vec3,
+    extent: f32,
+}
+
+fn extract_shadow_map(mut commands: Commands, shadow_map: Extract<Option<Res<CloudsShadowMap>>>) {
+    match shadow_map.as_deref() {
+        Some(shadow_map) => commands.insert_resource(ExtractedShadowMap {
+            image: shadow_map.image.id(),
+            center: shadow_map.center,
+            extent: shadow_map.extent,
+        }),
+        None => commands.remove_resource::<ExtractedShadowMap>(),
+    }
+}
+
+/// The shadow map that gets rendered this frame.
+#[derive(Resource)]
+pub(crate) struct PreparedShadowMap {
+    image: AssetId<Image>,
+    size: u32,
+    pub center: Vec3,
+    pub extent: f32,
+    /// Offset of the uniforms of the shadow map in
+    /// [`CloudsUniformBuffer`](crate::uniforms::CloudsUniformBuffer), set by
+    /// `prepare_uniforms_bind_group`.
+    pub uniform_offset: u32,
+}
+
+/// Select the shadow map for rendering once the noise textures are generated and the pipeline is
+/// compiled.
+fn prepare_shadow_map(
+    mut commands: Commands,
+    pipeline: Res<CloudsPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    noise_ready: Option<Res<CloudsNoiseReady>>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    shadow_map: Option<Res<ExtractedShadowMap>>,
+) {
+    let ready = noise_ready.is_some()
+        && pipeline_cache
+            .get_compute_pipeline(pipeline.shadow_map_pipeline)
+            .is_some();
+
+    if let Some(shadow_map) = shadow_map
+        && ready
+        && let Some(image) = gpu_images.get(shadow_map.image)
+    {
+        commands.insert_resource(PreparedShadowMap {
+            image: shadow_map.image,
+            size: image.size.width,
+            center: shadow_map.center,
+            extent: shadow_map.extent,
+            uniform_offset: 0,
+        });
+    } else {
+        commands.remove_resource::<PreparedShadowMap>();
+    }
+}
+
+#[derive(Resource)]
+struct ShadowMapBindGroup(BindGroup);
+
+fn prepare_shadow_map_bind_group(
+    mut commands: Commands,
+    pipeline: Res<CloudsPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    render_device: Res<RenderDevice>,
+    shadow_map: Option<Res<PreparedShadowMap>>,
+) {
+    let Some(image) = shadow_map.and_then(|shadow_map| gpu_images.get(shadow_map.image)) else {
+        commands.remove_resource::<ShadowMapBindGroup>();
+        return;
+    };
+    let bind_group = render_device.create_bind_group(
+        "clouds_shadow_map_bind_group",
+        &pipeline_cache.get_bind_group_layout(&pipeline.shadow_map_bind_group_layout),
+        &BindGroupEntries::with_indices(((7, &image.texture_view),)),
+    );
+    commands.insert_resource(ShadowMapBindGroup(bind_group));
+}
+
+/// Renders the [`CloudsShadowMap`], before the cameras that use it are rendered.
+pub(crate) struct CloudsShadowMapNode;
+
+impl Node for CloudsShadowMapNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let Some(shadow_map) = world.get_resource::<PreparedShadowMap>() else {
+            return Ok(());
+        };
+        let Some(shadow_map_bind_group) = world.get_resource::<ShadowMapBindGroup>() else {
+            return Ok(());
+        };
+        let Some(noise_bind_group) = world.get_resource::<CloudsNoiseBindGroup>() else {
+            return Ok(());
+        };
+        let Some(uniform_bind_group) = world.get_resource::<CloudsUniformBindGroup>() else {
+            return Ok(());
+        };
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let Some(shadow_map_pipeline) = pipeline_cache
+            .get_compute_pipeline(world.resource::<CloudsPipeline>().shadow_map_pipeline)
+        else {
+            return Ok(());
+        };
+
+        let mut pass = render_context
+            .command_encoder()
+            .begin_compute_pass(&ComputePassDescriptor::default());
+        pass.set_pipeline(shadow_map_pipeline);
+        pass.set_bind_group(0, &uniform_bind_group.0, &[shadow_map.uniform_offset]);
+        pass.set_bind_group(1, &noise_bind_group.0, &[]);
+        pass.set_bind_group(2, &shadow_map_bind_group.0, &[]);
+        let workgroups = shadow_map.size.div_ceil(WORKGROUP_SIZE);
+        pass.dispatch_workgroups(workgroups, workgroups, 1);
+
+        Ok(())
+    }
+}
+
+/// A plugin which renders the [`CloudsShadowMap`] and shadows [`CloudsShadowsMaterial`]s.
+pub(crate) struct CloudsShadowsPlugin;
+
+impl Plugin for CloudsShadowsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<CloudsShadowsMaterial>::default())
+            .add_systems(
+                PostUpdate,
+                (
+                    update_shadow_map_center
+                        .run_if(resource_exists::<CloudsShadowMap>)
+                        .after(TransformSystems::Propagate),
+                    update_shadow_materials,
+                )
+                    .chain()
+                    .after(update_sun_from_light),
+            );
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_systems(ExtractSchedule, extract_shadow_map)
+            .add_systems(
+                Render,
+                (
+                    prepare_shadow_map
+                        .in_set(RenderSystems::PrepareResources)
+                        .before(prepare_uniforms_bind_group),
+                    prepare_shadow_map_bind_group.in_set(RenderSystems::PrepareBindGroups),
+                ),
+            );
+
+        let mut render_graph = render_app.world_mut().resource_mut::<RenderGraph>();
+        render_graph.add_node(CloudsLabel::ShadowMap, CloudsShadowMapNode);
+        render_graph.add_node_edge(CloudsLabel::Init, CloudsLabel::ShadowMap);
+        render_graph.add_node_edge(
+            CloudsLabel::ShadowMap,
+            bevy::render::graph::CameraDriverLabel,
+        );
+    }
+}
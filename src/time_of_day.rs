@@ -0,0 +1,393 @@
+use core::f32::consts::{PI, TAU};
+
+use bevy::{light::light_consts::lux::FULL_DAYLIGHT, prelude::*};
+
+use crate::{config::CloudsConfig, light::CloudsSun, light::sun_color};
+
+/// Elevation of the center of the sun at sunrise and sunset, which accounts for the refraction of
+/// the atmosphere and the size of the sun.
+const SUNRISE_ELEVATION: f32 = -0.833 * PI / 180.0;
+
+/// Elevation of the sun at the end of nautical twilight, below which the sky is dark.
+const TWILIGHT_ELEVATION: f32 = -12.0 * PI / 180.0;
+
+/// Elevation of the sun above which the sky is fully lit.
+const DAYLIGHT_ELEVATION: f32 = 10.0 * PI / 180.0;
+
+/// Optical depth of the atmosphere towards the zenith for red, green and blue light.
+const ZENITH_OPTICAL_DEPTH: Vec3 = Vec3::new(0.15, 0.22, 0.38);
+
+/// Obliquity of the ecliptic, the tilt of the axis of the Earth.
+const OBLIQUITY: f32 = 23.44 * PI / 180.0;
+
+/// Length of the cycle of the phases of the moon, in days.
+const SYNODIC_MONTH: f32 = 29.53;
+
+/// Ambient colors of the clouds at night, relative to their colors during the day.
+const NIGHT_AMBIENT: f32 = 0.02;
+
+/// A day/night cycle which moves the sun of the clouds across the sky.
+///
+/// The position of the sun is computed from the time of day, the date and the location on Earth
+/// using the equations of NOAA's solar calculator. While this resource exists, the light with
+/// [`CloudsSun`] follows the sun, or [`CloudsConfig::sun_dir`] and [`CloudsConfig::sun_color`] if
+/// there is no such light. The ambient colors of the clouds get darker at night, when the moon
+/// lights the clouds through [`CloudsConfig::moon_dir`] and [`CloudsConfig::moon_color`]. The
+/// ambient colors and the moon color of the [`CloudsConfig`] at the time this resource is inserted
+/// are kept as their colors at full daylight and full moon.
+///
+/// The world is oriented with north towards -Z, east towards +X and up towards +Y. A [`Sunrise`]
+/// and a [`Sunset`] message are written when the sun crosses the horizon.
+///
+/// # Example
+///
+/// ```rust ignore
+/// App::new()
+///     .add_plugins((DefaultPlugins, CloudsPlugin))
+///     .insert_resource(TimeOfDay {
+///         hour: 18.5,
+///         time_scale: 120.0,
+///         ..default()
+///     })
+///     .run();
+/// ```
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct TimeOfDay {
+    /// Hours since midnight in UTC, between 0 and 24.
+    pub hour: f32,
+    /// Day of the year, between 1 and 365.
+    pub day_of_year: u32,
+    /// Latitude in degrees, positive towards the north.
+    pub latitude: f32,
+    /// Longitude in degrees, positive towards the east.
+    pub longitude: f32,
+    /// Phase of the moon, 0 at new moon and 0.5 at full moon, which advances by 1 every lunar
+    /// month of 29.53 days.
+    pub moon_phase: f32,
+    /// How fast the time of day passes compared to [`Time`], 0 stops the day/night cycle.
+    pub time_scale: f32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            hour: 10.0,
+            day_of_year: 172,
+            latitude: 45.0,
+            longitude: 0.0,
+            moon_phase: 0.5,
+            time_scale: 1.0,
+        }
+    }
+}
+
+impl TimeOfDay {
+    /// Direction towards the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        let (declination, hour_angle) = self.sun_position();
+        self.horizontal_direction(declination, hour_angle)
+    }
+
+    /// Angle of the sun above the horizon, in radians.
+    pub fn sun_elevation(&self) -> f32 {
+        ops::asin(self.sun_direction().y.clamp(-1.0, 1.0))
+    }
+
+    /// Direction towards the moon.
+    ///
+    /// The moon is placed on the ecliptic ahead of the sun by [`TimeOfDay::moon_phase`], ignoring
+    /// the inclination of its orbit.
+    pub fn moon_direction(&self) -> Vec3 {
+        // Ecliptic longitude of the sun, which is 0 at the March equinox.
+        let sun_longitude = TAU / 365.0 * (self.day_of_year as f32 - 80.0 + self.hour / 24.0);
+        let moon_longitude = sun_longitude + TAU * self.moon_phase;
+        let (sun_right_ascension, _) = equatorial_position(sun_longitude);
+        let (moon_right_ascension, moon_declination) = equatorial_position(moon_longitude);
+
+        // Objects with a larger right ascension cross the meridian later.
+        let (_, sun_hour_angle) = self.sun_position();
+        let hour_angle = sun_hour_angle + sun_right_ascension - moon_right_ascension;
+        self.horizontal_direction(moon_declination, hour_angle)
+    }
+
+    /// Angle of the moon above the horizon, in radians.
+    pub fn moon_elevation(&self) -> f32 {
+        ops::asin(self.moon_direction().y.clamp(-1.0, 1.0))
+    }
+
+    /// The declination and the hour angle of the sun, in radians.
+    fn sun_position(&self) -> (f32, f32) {
+        // Fractional year in radians.
+        let year = TAU / 365.0 * (self.day_of_year as f32 - 1.0 + (self.hour - 12.0) / 24.0);
+        let equation_of_time = 229.18
+            * (0.000075 + 0.001868 * ops::cos(year)
+                - 0.032077 * ops::sin(year)
+                - 0.014615 * ops::cos(2.0 * year)
+                - 0.040849 * ops::sin(2.0 * year));
+        let declination = 0.006918 - 0.399912 * ops::cos(year) + 0.070257 * ops::sin(year)
+            - 0.006758 * ops::cos(2.0 * year)
+            + 0.000907 * ops::sin(2.0 * year)
+            - 0.002697 * ops::cos(3.0 * year)
+            + 0.00148 * ops::sin(3.0 * year);
+
+        // The hour angle is 0 when the sun is highest, the true solar time is in minutes.
+        let true_solar_time = self.hour * 60.0 + equation_of_time + 4.0 * self.longitude;
+        let hour_angle = (true_solar_time / 4.0 - 180.0).to_radians();
+        (declination, hour_angle)
+    }
+
+    /// Direction towards an object in the sky with the given declination and hour angle.
+    fn horizontal_direction(&self, declination: f32, hour_angle: f32) -> Vec3 {
+        let latitude = self.latitude.to_radians();
+        let east = -ops::cos(declination) * ops::sin(hour_angle);
+        let north = ops::cos(latitude) * ops::sin(declination)
+            - ops::sin(latitude) * ops::cos(declination) * ops::cos(hour_angle);
+        let up = ops::sin(latitude) * ops::sin(declination)
+            + ops::cos(latitude) * ops::cos(declination) * ops::cos(hour_angle);
+        Vec3::new(east, up, -north).normalize()
+    }
+
+    fn advance(&mut self, delta_secs: f32) {
+        let hours = delta_secs * self.time_scale / 3600.0;
+        self.moon_phase = (self.moon_phase + hours / (24.0 * SYNODIC_MONTH)).rem_euclid(1.0);
+        self.hour += hours;
+        let days = self.hour.div_euclid(24.0);
+        self.hour = self.hour.rem_euclid(24.0);
+        self.day_of_year = (self.day_of_year as i64 - 1 + days as i64).rem_euclid(365) as u32 + 1;
+    }
+}
+
+/// Written when the sun rises above the horizon, see [`TimeOfDay`].
+#[derive(Message, Clone, Copy, Debug)]
+pub struct Sunrise;
+
+/// Written when the sun sets below the horizon, see [`TimeOfDay`].
+#[derive(Message, Clone, Copy, Debug)]
+pub struct Sunset;
+
+/// The right ascension and the declination of a point on the ecliptic with the given longitude, in
+/// radians.
+fn equatorial_position(longitude: f32) -> (f32, f32) {
+    let right_ascension = ops::atan2(
+        ops::cos(OBLIQUITY) * ops::sin(longitude),
+        ops::cos(longitude),
+    );
+    let declination = ops::asin(ops::sin(OBLIQUITY) * ops::sin(longitude));
+    (right_ascension, declination)
+}
+
+/// The color and illuminance of sunlight that passed through the atmosphere at the given elevation.
+fn sunlight(elevation: f32) -> (Color, f32) {
+    if elevation < SUNRISE_ELEVATION {
+        return (Color::WHITE, 0.0);
+    }
+
+    // The air mass relative to the zenith, following Kasten and Young.
+    let zenith_angle = (PI / 2.0 - elevation).min(PI / 2.0);
+    let air_mass = 1.0
+        / (ops::cos(zenith_angle)
+            + 0.50572 * ops::powf(96.07995 - zenith_angle.to_degrees(), -1.6364));
+
+    // Relative to the sunlight at the zenith, such that the sun is white at noon.
+    let transmittance = (-ZENITH_OPTICAL_DEPTH * (air_mass - 1.0)).exp();
+    let illuminance = FULL_DAYLIGHT * transmittance.max_element();
+    let color = transmittance / transmittance.max_element();
+    (Color::linear_rgb(color.x, color.y, color.z), illuminance)
+}
+
+/// Advance the [`TimeOfDay`] and move the sun and the moon of the clouds accordingly.
+#[expect(clippy::too_many_arguments)]
+pub(crate) fn update_time_of_day(
+    time: Res<Time>,
+    mut time_of_day: ResMut<TimeOfDay>,
+    mut config: ResMut<CloudsConfig>,
+    mut suns: Query<(&mut DirectionalLight, &mut Transform), With<CloudsSun>>,
+    mut sunrises: MessageWriter<Sunrise>,
+    mut sunsets: MessageWriter<Sunset>,
+    mut was_day: Local<Option<bool>>,
+    mut base_colors: Local<Option<(Vec4, Vec4, Vec4)>>,
+) {
+    // The colors that the cycle scales are captured before it changes them for the first time.
+    if time_of_day.is_added() {
+        *base_colors = None;
+    }
+    let (ambient_color_top, ambient_color_bottom, moon_color) = *base_colors.get_or_insert((
+        config.clouds_ambient_color_top,
+        config.clouds_ambient_color_bottom,
+        config.moon_color,
+    ));
+
+    time_of_day.advance(time.delta_secs());
+
+    let sun_dir = time_of_day.sun_direction();
+    let elevation = ops::asin(sun_dir.y.clamp(-1.0, 1.0));
+    let (color, illuminance) = sunlight(elevation);
+
+    let is_day = elevation >= SUNRISE_ELEVATION;
+    match (*was_day, is_day) {
+        (Some(false), true) => {
+            sunrises.write(Sunrise);
+        }
+        (Some(true), false) => {
+            sunsets.write(Sunset);
+        }
+        _ => {}
+    }
+    *was_day = Some(is_day);
+
+    if let Ok((mut light, mut transform)) = suns.single_mut() {
+        // The light is copied to the configuration by `update_sun_from_light`.
+        light.color = color;
+        light.illuminance = illuminance;
+        transform.rotation = Transform::default().looking_to(-sun_dir, Vec3::Y).rotation;
+    } else {
+        config.sun_dir = sun_dir.extend(0.0);
+        config.sun_color = sun_color(color, illuminance);
+    }
+
+    // The sky lights the clouds during the day and fades out during twilight, when it takes on
+    // some of the color of the sun.
+    let daylight = EaseFunction::SmoothStep.sample_clamped(
+        (elevation - TWILIGHT_ELEVATION) / (DAYLIGHT_ELEVATION - TWILIGHT_ELEVATION),
+    );
+    let tint = color
+        .to_linear()
+        .to_vec4()
+        .lerp(Vec4::ONE, 0.5 + 0.5 * daylight);
+    let ambient = tint * (NIGHT_AMBIENT + (1.0 - NIGHT_AMBIENT) * daylight);
+    config.clouds_ambient_color_top = ambient_color_top * ambient;
+    config.clouds_ambient_color_bottom = ambient_color_bottom * ambient;
+
+    // Moonlight passes through the air like sunlight. It is outshone by the sky during the day,
+    // where it isn't worth the extra shadow raymarch.
+    let moon_dir = time_of_day.moon_direction();
+    let (moon_tint, moon_illuminance) = sunlight(ops::asin(moon_dir.y.clamp(-1.0, 1.0)));
+    let moonlight = moon_illuminance / FULL_DAYLIGHT * (1.0 - daylight);
+    config.moon_dir = moon_dir.extend(0.0);
+    config.moon_color =
+        (moon_color.truncate() * moon_tint.to_linear().to_vec3() * moonlight).extend(1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+
+    /// The highest elevation of the sun on a day, in degrees.
+    fn noon_elevation(latitude: f32, day_of_year: u32) -> f32 {
+        (0..24 * 60)
+            .map(|minute| {
+                TimeOfDay {
+                    hour: minute as f32 / 60.0,
+                    day_of_year,
+                    latitude,
+                    ..default()
+                }
+                .sun_elevation()
+                .to_degrees()
+            })
+            .fold(f32::MIN, f32::max)
+    }
+
+    #[test]
+    fn sun_elevation_at_solar_noon() {
+        // At noon, the sun is 90° minus the latitude plus the declination above the horizon.
+        let march_equinox = 80;
+        let june_solstice = 172;
+        let december_solstice = 355;
+        assert!((noon_elevation(52.0, march_equinox) - 38.0).abs() < 0.5);
+        assert!((noon_elevation(52.0, june_solstice) - 61.44).abs() < 0.5);
+        assert!((noon_elevation(52.0, december_solstice) - 14.56).abs() < 0.5);
+        assert!((noon_elevation(-33.9, june_solstice) - 32.66).abs() < 0.5);
+    }
+
+    #[test]
+    fn advance_wraps_past_the_end_of_the_day_and_year() {
+        let mut time_of_day = TimeOfDay {
+            hour: 23.0,
+            day_of_year: 364,
+            ..default()
+        };
+        time_of_day.advance(2.0 * 3600.0);
+        assert!((time_of_day.hour - 1.0).abs() < 1.0e-3);
+        assert_eq!(time_of_day.day_of_year, 365);
+
+        time_of_day.advance(24.0 * 3600.0);
+        assert!((time_of_day.hour - 1.0).abs() < 1.0e-3);
+        assert_eq!(time_of_day.day_of_year, 1);
+
+        time_of_day.time_scale = -1.0;
+        time_of_day.advance(2.0 * 3600.0);
+        assert!((time_of_day.hour - 23.0).abs() < 1.0e-3);
+        assert_eq!(time_of_day.day_of_year, 365);
+    }
+
+    #[test]
+    fn one_message_per_sunrise_and_sunset() {
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(CloudsConfig::default());
+        world.insert_resource(TimeOfDay {
+            hour: 0.0,
+            latitude: 52.0,
+            // Every update passes 6 minutes.
+            time_scale: 3600.0,
+            ..default()
+        });
+        world.init_resource::<Messages<Sunrise>>();
+        world.init_resource::<Messages<Sunset>>();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(update_time_of_day);
+
+        // Two days and nights.
+        for _ in 0..2 * 24 * 10 {
+            world
+                .resource_mut::<Time>()
+                .advance_by(Duration::from_millis(100));
+            schedule.run(&mut world);
+        }
+
+        assert_eq!(world.resource::<Messages<Sunrise>>().len(), 2);
+        assert_eq!(world.resource::<Messages<Sunset>>().len(), 2);
+    }
+
+    #[test]
+    fn ambient_colors_keep_their_hue() {
+        let ambient_color_top = Vec4::new(0.2, 0.4, 0.8, 1.0);
+        let ambient_color_bottom = Vec4::new(0.6, 0.3, 0.1, 1.0);
+        let mut world = World::new();
+        world.insert_resource(Time::<()>::default());
+        world.insert_resource(CloudsConfig {
+            clouds_ambient_color_top: ambient_color_top,
+            clouds_ambient_color_bottom: ambient_color_bottom,
+            ..default()
+        });
+        // Midnight, when the sun is well below the horizon.
+        world.insert_resource(TimeOfDay {
+            hour: 0.0,
+            time_scale: 0.0,
+            ..default()
+        });
+        world.init_resource::<Messages<Sunrise>>();
+        world.init_resource::<Messages<Sunset>>();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(update_time_of_day);
+
+        // The colors are scaled from the configured ones on every update, not from the last ones.
+        for _ in 0..2 {
+            schedule.run(&mut world);
+            let config = world.resource::<CloudsConfig>();
+            assert!(
+                config
+                    .clouds_ambient_color_top
+                    .abs_diff_eq(ambient_color_top * NIGHT_AMBIENT, 1.0e-6)
+            );
+            assert!(
+                config
+                    .clouds_ambient_color_bottom
+                    .abs_diff_eq(ambient_color_bottom * NIGHT_AMBIENT, 1.0e-6)
+            );
+        }
+    }
+}
@@ -4,7 +4,7 @@ use bevy_egui::{
     egui::{self, Color32, Pos2, Ui},
 };
 
-use super::config::CloudsConfig;
+use super::config::{CloudsConfig, Interleaving, RenderScale};
 
 #[expect(dead_code)]
 fn color_picker(title: &str, color: &mut Vec4, ui: &mut Ui) {
@@ -41,11 +41,20 @@ pub fn clouds_ui(config: &mut CloudsConfig, ui: &mut Ui) {
     ui.end_row();
     ui.add(egui::Slider::new(&mut config.clouds_coverage, 0.0..=1.0).text("clouds_coverage"));
     ui.end_row();
+    ui.add(egui::Slider::new(&mut config.cloud_type, 0.0..=1.0).text("cloud_type"));
+    ui.end_row();
     ui.add(
         egui::Slider::new(&mut config.clouds_detail_strength, 0.0..=1.0)
             .text("clouds_detail_strength"),
     );
     ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.clouds_curl_strength, 0.0..=200.0)
+            .text("clouds_curl_strength"),
+    );
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut config.clouds_curl_scale, 0.1..=50.0).text("clouds_curl_scale"));
+    ui.end_row();
     ui.add(
         egui::Slider::new(&mut config.clouds_base_edge_softness, 0.0..=1.0)
             .text("clouds_base_edge_softness"),
@@ -80,6 +89,43 @@ pub fn clouds_ui(config: &mut CloudsConfig, ui: &mut Ui) {
     ui.end_row();
     ui.add(egui::Slider::new(&mut config.scattering_lerp, 0.01..=100.0).text("Scattering lerp"));
     ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.multiple_scattering_octaves, 1..=8)
+            .text("Multiple scattering octaves"),
+    );
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.multiple_scattering_attenuation, 0.0..=1.0)
+            .text("Multiple scattering attenuation"),
+    );
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.multiple_scattering_contribution, 0.0..=1.0)
+            .text("Multiple scattering contribution"),
+    );
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.multiple_scattering_eccentricity, 0.0..=1.0)
+            .text("Multiple scattering eccentricity"),
+    );
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut config.powder_strength, 0.0..=1.0).text("Powder strength"));
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.crepuscular_rays_intensity, 0.0..=2.0)
+            .text("Crepuscular rays intensity"),
+    );
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.crepuscular_rays_decay, 0.8..=1.0)
+            .text("Crepuscular rays decay"),
+    );
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut config.crepuscular_rays_samples, 1..=128)
+            .text("Crepuscular rays samples"),
+    );
+    ui.end_row();
     ui.add(
         egui::Slider::new(&mut config.clouds_min_transmittance, 0.01..=100.0)
             .text("Min transmittance"),
@@ -96,6 +142,28 @@ pub fn clouds_ui(config: &mut CloudsConfig, ui: &mut Ui) {
             .text("reprojection_strength"),
     );
     ui.end_row();
+    ui.add(egui::Label::new("render_scale"));
+    ui.end_row();
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut config.render_scale, RenderScale::Full, "Full");
+        ui.radio_value(&mut config.render_scale, RenderScale::Half, "Half");
+        ui.radio_value(&mut config.render_scale, RenderScale::Third, "Third");
+        ui.radio_value(&mut config.render_scale, RenderScale::Quarter, "Quarter");
+    });
+    ui.end_row();
+    ui.add(egui::Label::new("interleaving"));
+    ui.end_row();
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut config.interleaving, Interleaving::None, "None");
+        ui.radio_value(
+            &mut config.interleaving,
+            Interleaving::Checkerboard,
+            "Checkerboard",
+        );
+        ui.radio_value(&mut config.interleaving, Interleaving::Bayer2x2, "2x2");
+        ui.radio_value(&mut config.interleaving, Interleaving::Bayer4x4, "4x4");
+    });
+    ui.end_row();
     ui.add(egui::Label::new("wind_velocity"));
     ui.end_row();
     ui.add(egui::Slider::new(&mut config.wind_velocity.x, -100.0..=100.0).text("x"));
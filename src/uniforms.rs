@@ -1,11 +1,33 @@
+use core::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
 use bevy::{
     prelude::*,
     render::{
+        extract_component::ExtractComponent,
         extract_resource::ExtractResource,
-        render_resource::{AsBindGroup, ShaderType, UniformBuffer},
+        render_resource::{DynamicUniformBuffer, ShaderType},
     },
 };
 
+use crate::layers::MAX_CLOUD_LAYERS;
+
+/// A layer of [`CloudsLayers`](crate::layers::CloudsLayers) in the [`CloudsUniform`].
+#[derive(Clone, Copy, Default, Reflect, ShaderType)]
+pub(crate) struct CloudLayerUniform {
+    pub wind_displacement: Vec3,
+    pub previous_wind_displacement: Vec3,
+    pub bottom_height: f32,
+    pub top_height: f32,
+    pub coverage: f32,
+    pub density: f32,
+    pub base_scale: f32,
+    pub detail_scale: f32,
+    pub cloud_type: f32,
+    pub mode: u32,
+    pub use_weather_map: u32,
+}
+
 #[derive(Clone, Resource, ExtractResource, Reflect, ShaderType)]
 #[reflect(Resource, Default)]
 pub(crate) struct CloudsUniform {
@@ -14,9 +36,12 @@ pub(crate) struct CloudsUniform {
     pub clouds_bottom_height: f32,
     pub clouds_top_height: f32,
     pub clouds_coverage: f32,
+    pub cloud_type: f32,
     pub clouds_density: f32,
     pub clouds_detail_scale: f32,
     pub clouds_detail_strength: f32,
+    pub clouds_curl_strength: f32,
+    pub clouds_curl_scale: f32,
     pub clouds_base_edge_softness: f32,
     pub clouds_bottom_softness: f32,
     pub clouds_shadow_raymarch_steps_count: u32,
@@ -29,8 +54,19 @@ pub(crate) struct CloudsUniform {
     pub forward_scattering_g: f32,
     pub backward_scattering_g: f32,
     pub scattering_lerp: f32,
+    pub multiple_scattering_octaves: u32,
+    pub multiple_scattering_attenuation: f32,
+    pub multiple_scattering_contribution: f32,
+    pub multiple_scattering_eccentricity: f32,
+    pub powder_strength: f32,
     pub sun_dir: Vec4,
     pub sun_color: Vec4,
+    pub moon_dir: Vec4,
+    pub moon_color: Vec4,
+    pub stars_intensity: f32,
+    pub crepuscular_rays_intensity: f32,
+    pub crepuscular_rays_decay: f32,
+    pub crepuscular_rays_samples: u32,
     pub camera_translation: Vec3,
     pub time: f32,
     pub reprojection_strength: f32,
@@ -38,6 +74,20 @@ pub(crate) struct CloudsUniform {
     pub inverse_camera_view: Mat4,
     pub inverse_camera_projection: Mat4,
     pub wind_displacement: Vec3,
+    pub previous_wind_displacement: Vec3,
+    pub previous_camera_translation: Vec3,
+    pub clip_from_world: Mat4,
+    pub previous_clip_from_world: Mat4,
+    pub frame_index: u32,
+    pub history_valid: u32,
+    pub interleave_tile_size: UVec2,
+    pub viewport: Vec4,
+    pub shadow_map_center: Vec3,
+    pub shadow_map_extent: f32,
+    pub weather_map_center: Vec2,
+    pub weather_map_extent: Vec2,
+    pub layer_count: u32,
+    pub layers: [CloudLayerUniform; MAX_CLOUD_LAYERS],
 }
 
 impl Default for CloudsUniform {
@@ -49,7 +99,10 @@ impl Default for CloudsUniform {
             clouds_bottom_height: 0.,
             clouds_top_height: 0.,
             clouds_coverage: 0.0,
+            cloud_type: 0.0,
             clouds_detail_strength: 0.0,
+            clouds_curl_strength: 0.0,
+            clouds_curl_scale: 0.0,
             clouds_base_edge_softness: 0.0,
             clouds_bottom_softness: 0.0,
             clouds_density: 0.0,
@@ -58,6 +111,11 @@ impl Default for CloudsUniform {
             forward_scattering_g: 0.0,
             backward_scattering_g: 0.0,
             scattering_lerp: 0.0,
+            multiple_scattering_octaves: 1,
+            multiple_scattering_attenuation: 0.0,
+            multiple_scattering_contribution: 0.0,
+            multiple_scattering_eccentricity: 0.0,
+            powder_strength: 0.0,
             clouds_ambient_color_top: Vec4::ZERO,
             clouds_ambient_color_bottom: Vec4::ZERO,
             clouds_min_transmittance: 0.0,
@@ -65,6 +123,12 @@ impl Default for CloudsUniform {
             clouds_detail_scale: 0.0,
             sun_dir: Vec4::ZERO,
             sun_color: Vec4::ZERO,
+            moon_dir: Vec4::ZERO,
+            moon_color: Vec4::ZERO,
+            stars_intensity: 0.0,
+            crepuscular_rays_intensity: 0.0,
+            crepuscular_rays_decay: 0.0,
+            crepuscular_rays_samples: 0,
             camera_translation: Vec3::ZERO,
             time: 0.0,
             reprojection_strength: 0.95,
@@ -72,26 +136,70 @@ impl Default for CloudsUniform {
             inverse_camera_view: Mat4::IDENTITY,
             inverse_camera_projection: Mat4::IDENTITY,
             wind_displacement: Vec3::new(-11.0, 0.0, 23.0),
+            previous_wind_displacement: Vec3::new(-11.0, 0.0, 23.0),
+            previous_camera_translation: Vec3::ZERO,
+            clip_from_world: Mat4::IDENTITY,
+            previous_clip_from_world: Mat4::IDENTITY,
+            frame_index: 0,
+            history_valid: 0,
+            interleave_tile_size: UVec2::ONE,
+            viewport: Vec4::ZERO,
+            shadow_map_center: Vec3::ZERO,
+            shadow_map_extent: 0.0,
+            weather_map_center: Vec2::ZERO,
+            weather_map_extent: Vec2::ZERO,
+            layer_count: 0,
+            layers: [CloudLayerUniform::default(); MAX_CLOUD_LAYERS],
         }
     }
 }
 
+/// The uniforms of all views with [`VolumetricClouds`](crate::config::VolumetricClouds).
 #[derive(Resource, Default)]
 pub(crate) struct CloudsUniformBuffer {
-    pub buffer: UniformBuffer<CloudsUniform>,
+    /// The values shared by all views, which are updated once per frame.
+    pub uniform: CloudsUniform,
+    pub buffer: DynamicUniformBuffer<CloudsUniform>,
 }
 
-#[derive(Resource, Clone, ExtractResource, AsBindGroup)]
-pub(crate) struct CloudsImage {
-    #[storage_texture(0, image_format = Rgba32Float, access = ReadWrite)]
-    pub cloud_render_image: Handle<Image>,
-
-    #[storage_texture(1, image_format = Rgba32Float, access = ReadWrite)]
+/// The noise textures of the [`CloudsNoise`](crate::noise::CloudsNoise), which are shared by all
+/// views.
+///
+/// The `init` and `init_worley` entry points of the noise shader generate them, writing the first
+/// mip level of the Worley noise.
+#[derive(Resource, Clone, ExtractResource)]
+pub(crate) struct CloudsNoiseImages {
+    /// The tiling 2D texture of the shape of the clouds.
     pub cloud_atlas_image: Handle<Image>,
 
-    #[storage_texture(2, image_format = Rgba32Float, access = ReadWrite, dimension = "3d")]
+    /// The tiling 3D texture of the details of the clouds.
     pub cloud_worley_image: Handle<Image>,
 
-    #[storage_texture(3, image_format = Rgba32Float, access = ReadWrite)]
+    /// The tiling 2D texture of the directions which distort the details of the clouds.
+    pub cloud_curl_image: Handle<Image>,
+
+    /// Set once the noise has been generated, `None` for noise that doesn't get generated.
+    pub generated: Option<Arc<AtomicBool>>,
+}
+
+/// The textures the clouds and sky of a camera get rendered to every frame.
+#[derive(Component, Clone, ExtractComponent)]
+pub(crate) struct CloudsRenderImages {
+    /// The clouds after temporal reprojection, which get composited onto the screen.
+    pub cloud_render_image: Handle<Image>,
+
+    /// The sky behind the clouds.
     pub sky_image: Handle<Image>,
+
+    /// Distance to the clouds, used for reprojection and to upsample the clouds.
+    pub cloud_depth_image: Handle<Image>,
+
+    /// The clouds raymarched (or interpolated) in the current frame.
+    pub cloud_raymarch_image: Handle<Image>,
+
+    /// Copy of `cloud_render_image` of the previous frame.
+    pub cloud_history_image: Handle<Image>,
+
+    /// Copy of `cloud_depth_image` of the previous frame.
+    pub cloud_history_depth_image: Handle<Image>,
 }
@@ -0,0 +1,136 @@
+use bevy::{
+    prelude::*,
+    render::{
+        Extract, ExtractSchedule, Render, RenderApp, RenderSystems,
+        render_asset::RenderAssets,
+        render_resource::{Sampler, TextureView},
+        texture::{FallbackImage, GpuImage},
+    },
+};
+
+use crate::compute::{prepare_noise_bind_group, prepare_uniforms_bind_group};
+
+/// A weather map which controls where clouds form, for example to paint where storms and clear
+/// skies are, following the weather maps of Horizon Zero Dawn.
+///
+/// The channels of the image hold the weather at each point of the ground plane:
+/// - red: the coverage of the clouds, which replaces [`CloudsConfig::clouds_coverage`].
+/// - green: the type of the clouds, which selects a profile of the
+///   [`CloudsProfiles`](crate::profiles::CloudsProfiles) and replaces
+///   [`CloudsConfig::cloud_type`](crate::config::CloudsConfig::cloud_type).
+/// - blue: the wetness of the clouds, which makes them denser and darker like rain clouds.
+///
+/// The map stays in place while the clouds drift through it with the wind. It is sampled with the
+/// sampler of the image, whose address mode determines what lies beyond the edges of the map. The
+/// image needs a filterable format and linear color data, so load it with `is_srgb` set to false.
+///
+/// # Example
+///
+/// ```rust ignore
+/// fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+///     let image = asset_server.load_with_settings(
+///         "weather.png",
+///         |settings: &mut ImageLoaderSettings| settings.is_srgb = false,
+///     );
+///     commands.insert_resource(CloudsWeatherMap::new(image, Vec2::splat(50_000.0)));
+/// }
+/// ```
+///
+/// [`CloudsConfig::clouds_coverage`]: crate::config::CloudsConfig::clouds_coverage
+#[derive(Resource, Clone, Debug)]
+pub struct CloudsWeatherMap {
+    /// The weather map.
+    pub image: Handle<Image>,
+    /// Center of the region of the ground plane covered by the map, along the X and Z axes.
+    pub center: Vec2,
+    /// Size of the region of the ground plane covered by the map, in world units.
+    pub extent: Vec2,
+}
+
+impl CloudsWeatherMap {
+    /// Creates a weather map covering `extent` world units around the origin.
+    pub fn new(image: Handle<Image>, extent: Vec2) -> Self {
+        Self {
+            image,
+            center: Vec2::ZERO,
+            extent,
+        }
+    }
+}
+
+/// A [`CloudsWeatherMap`] in the render world.
+#[derive(Resource)]
+struct ExtractedWeatherMap {
+    image: AssetId<Image>,
+    center: Vec2,
+    extent: Vec2,
+}
+
+fn extract_weather_map(
+    mut commands: Commands,
+    weather_map: Extract<Option<Res<CloudsWeatherMap>>>,
+) {
+    match weather_map.as_deref() {
+        Some(weather_map) => commands.insert_resource(ExtractedWeatherMap {
+            image: weather_map.image.id(),
+            center: weather_map.center,
+            extent: weather_map.extent,
+        }),
+        None => commands.remove_resource::<ExtractedWeatherMap>(),
+    }
+}
+
+/// The weather map the clouds are rendered with this frame.
+///
+/// Without a (loaded) [`CloudsWeatherMap`], the fallback image is bound and `extent` is zero,
+/// which makes the shader use the [`CloudsConfig`](crate::config::CloudsConfig) instead.
+#[derive(Resource)]
+pub(crate) struct PreparedWeatherMap {
+    pub texture_view: TextureView,
+    pub sampler: Sampler,
+    pub center: Vec2,
+    pub extent: Vec2,
+}
+
+fn prepare_weather_map(
+    mut commands: Commands,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    fallback_image: Res<FallbackImage>,
+    weather_map: Option<Res<ExtractedWeatherMap>>,
+) {
+    let prepared = weather_map
+        .and_then(|weather_map| {
+            let image = gpu_images.get(weather_map.image)?;
+            Some(PreparedWeatherMap {
+                texture_view: image.texture_view.clone(),
+                sampler: image.sampler.clone(),
+                center: weather_map.center,
+                extent: weather_map.extent,
+            })
+        })
+        .unwrap_or_else(|| PreparedWeatherMap {
+            texture_view: fallback_image.d2.texture_view.clone(),
+            sampler: fallback_image.d2.sampler.clone(),
+            center: Vec2::ZERO,
+            extent: Vec2::ZERO,
+        });
+    commands.insert_resource(prepared);
+}
+
+/// A plugin which lets a [`CloudsWeatherMap`] shape the clouds.
+pub(crate) struct CloudsWeatherPlugin;
+
+impl Plugin for CloudsWeatherPlugin {
+    fn build(&self, app: &mut App) {
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app
+            .add_systems(ExtractSchedule, extract_weather_map)
+            .add_systems(
+                Render,
+                prepare_weather_map
+                    .in_set(RenderSystems::PrepareResources)
+                    .before(prepare_noise_bind_group)
+                    .before(prepare_uniforms_bind_group),
+            );
+    }
+}